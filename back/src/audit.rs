@@ -0,0 +1,196 @@
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use utoipa::{IntoParams, ToSchema};
+
+pub(crate) const SCHEDULER: &str = "scheduler";
+
+tokio::task_local! {
+    /// Method and route of the request being handled, recorded with the events it creates.
+    static ROUTE: (String, String);
+}
+
+/// Records the method and route of every request with the audit events it creates, so that the
+/// legacy and v1 routes doing the same action can be told apart.
+pub(crate) async fn route(req: Request, next: Next) -> Response {
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => req.uri().path().to_string(),
+    };
+    ROUTE
+        .scope((req.method().to_string(), route), next.run(req))
+        .await
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub(crate) struct Event {
+    pub(crate) time: DateTime<Utc>,
+    pub(crate) principal: String,
+    pub(crate) action: String,
+    /// Method of the request, none for the scheduler.
+    #[serde(default)]
+    pub(crate) method: Option<String>,
+    /// Route of the request, e.g. `/api/v1/accounts/{name}/session`, none for the scheduler.
+    #[serde(default)]
+    pub(crate) route: Option<String>,
+    pub(crate) account: Option<String>,
+    #[schema(value_type = Object)]
+    pub(crate) parameters: Value,
+    pub(crate) success: bool,
+    pub(crate) outcome: String,
+    pub(crate) cost: Option<f64>,
+    pub(crate) currency: Option<String>,
+}
+
+impl Event {
+    pub(crate) fn new(
+        principal: &str,
+        action: &str,
+        account: Option<&str>,
+        parameters: Value,
+    ) -> Self {
+        let (method, route) = ROUTE
+            .try_with(|(method, route)| (method.clone(), route.clone()))
+            .ok()
+            .unzip();
+        Self {
+            time: Utc::now(),
+            principal: principal.to_string(),
+            action: action.to_string(),
+            method,
+            route,
+            account: account.map(str::to_string),
+            parameters,
+            success: false,
+            outcome: String::new(),
+            cost: None,
+            currency: None,
+        }
+    }
+
+    pub(crate) fn succeeded(mut self, outcome: impl Into<String>) -> Self {
+        self.success = true;
+        self.outcome = outcome.into();
+        self
+    }
+
+    pub(crate) fn failed(mut self, outcome: impl Into<String>) -> Self {
+        self.success = false;
+        self.outcome = outcome.into();
+        self
+    }
+
+    pub(crate) fn cost(mut self, amount: f64, currency: &str) -> Self {
+        self.cost = Some(amount);
+        self.currency = Some(currency.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct Filter {
+    pub(crate) principal: Option<String>,
+    pub(crate) account: Option<String>,
+    pub(crate) action: Option<String>,
+    pub(crate) method: Option<String>,
+    pub(crate) route: Option<String>,
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) limit: Option<usize>,
+}
+
+/// Append-only log of the actions taken on the accounts, one JSON event per line.
+pub(crate) struct AuditLog {
+    path: Option<PathBuf>,
+    file: Mutex<()>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            file: Mutex::new(()),
+        }
+    }
+
+    /// An audit log that records nothing, for simulations.
+    pub(crate) fn disabled() -> Self {
+        Self {
+            path: None,
+            file: Mutex::new(()),
+        }
+    }
+
+    pub(crate) fn record(&self, event: Event) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let _guard = self.file.lock().unwrap();
+        let result = serde_json::to_string(&event)
+            .map_err(std::io::Error::from)
+            .and_then(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?
+                    .write_all(format!("{}\n", line).as_bytes())
+            });
+        if let Err(e) = result {
+            log::error!("Failed to write audit event {:?}: {:?}", event, e);
+        }
+    }
+
+    /// Returns the most recent events matching the filter, oldest first.
+    pub(crate) fn query(&self, filter: &Filter) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Box::new(e)),
+        };
+        let mut events: Vec<Event> = contents
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    log::warn!("Skipping malformed audit event: {:?}", e);
+                    None
+                }
+            })
+            .filter(|event: &Event| {
+                filter
+                    .principal
+                    .as_ref()
+                    .is_none_or(|p| &event.principal == p)
+                    && filter
+                        .account
+                        .as_ref()
+                        .is_none_or(|a| event.account.as_ref() == Some(a))
+                    && filter.action.as_ref().is_none_or(|a| &event.action == a)
+                    && filter
+                        .method
+                        .as_ref()
+                        .is_none_or(|m| event.method.as_ref() == Some(m))
+                    && filter
+                        .route
+                        .as_ref()
+                        .is_none_or(|r| event.route.as_ref() == Some(r))
+                    && filter.since.is_none_or(|since| event.time >= since)
+            })
+            .collect();
+        let limit = filter.limit.unwrap_or(100);
+        if events.len() > limit {
+            events.drain(..events.len() - limit);
+        }
+        Ok(events)
+    }
+}
@@ -1,37 +1,252 @@
+use crate::logging::REDACTED;
+use crate::types::ParkingSession;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub(crate) struct Accounts {
     pub(crate) accounts: Vec<Config>,
+    #[serde(default, skip_serializing)]
+    #[schema(ignore)]
+    pub(crate) settings: Settings,
+    #[serde(default, skip_serializing)]
+    #[schema(ignore)]
+    pub(crate) users: Vec<User>,
+    #[serde(default, skip_serializing)]
+    #[schema(ignore)]
+    pub(crate) oidc: Option<Oidc>,
+    #[serde(default, skip_serializing)]
+    #[schema(ignore)]
+    pub(crate) cors: Option<Cors>,
+}
+
+impl Accounts {
+    /// Takes the accounts, users and settings of a freshly read config, keeping the renewal plans
+    /// and unmanaged sessions of the accounts still configured. OIDC and CORS need a restart.
+    pub(crate) fn reload(&mut self, mut fresh: Accounts) {
+        for account in fresh.accounts.iter_mut() {
+            if let Some(current) = self.accounts.iter().find(|a| a.name == account.name) {
+                account.session = current.session.clone();
+                account.unmanaged = current.unmanaged.clone();
+            }
+        }
+        for account in self.accounts.iter() {
+            if account.session.as_ref().is_some_and(|s| s.is_active())
+                && !fresh.accounts.iter().any(|a| a.name == account.name)
+            {
+                log::warn!(
+                    "Account {} was removed, dropping its renewal plan",
+                    account.name
+                );
+            }
+        }
+        // A dry run, possibly forced by --dry-run, is never turned off by a reload.
+        fresh.settings.dry_run |= self.settings.dry_run;
+        self.accounts = fresh.accounts;
+        self.users = fresh.users;
+        self.settings = fresh.settings;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    Viewer,
+    Parker,
+    Admin,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub(crate) struct User {
+    pub(crate) name: String,
+    #[serde(default, skip_serializing)]
+    #[schema(ignore)]
+    pub(crate) token_hash: String,
+    #[serde(default, skip_serializing)]
+    #[schema(ignore)]
+    pub(crate) password_hash: Option<String>,
+    pub(crate) role: Role,
+    #[serde(default)]
+    pub(crate) accounts: Vec<String>,
+}
+
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("name", &self.name)
+            .field("role", &self.role)
+            .field("accounts", &self.accounts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl User {
+    pub(crate) fn can_access(&self, account_name: &str) -> bool {
+        self.accounts.iter().any(|a| a == "*" || a == account_name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Oidc {
+    pub(crate) issuer: String,
+    pub(crate) audience: String,
+    /// URL or path of the JSON Web Key Set used to verify the signature of the tokens.
+    pub(crate) jwks: String,
+    #[serde(default = "default_user_claim")]
+    pub(crate) user_claim: String,
+    #[serde(default)]
+    pub(crate) role_claim: Option<String>,
+    #[serde(default)]
+    pub(crate) accounts_claim: Option<String>,
+}
+
+fn default_user_claim() -> String {
+    "sub".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Cors {
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) allowed_methods: Vec<String>,
+    pub(crate) allowed_headers: Vec<String>,
+    pub(crate) allow_credentials: bool,
+    pub(crate) max_age: u64,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "PATCH".to_string()],
+            allowed_headers: vec![
+                "authorization".to_string(),
+                "content-type".to_string(),
+                "idempotency-key".to_string(),
+            ],
+            allow_credentials: true,
+            max_age: 3600,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub(crate) stop_on_external_stop: bool,
+    pub(crate) verify_lead: i64,
+    pub(crate) discovery_interval: u64,
+    pub(crate) dry_run: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            stop_on_external_stop: true,
+            verify_lead: 2,
+            discovery_interval: 5,
+            dry_run: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub(crate) struct Config {
     pub(crate) name: String,
     pub(crate) plate: String,
     pub(crate) lot: i32,
     #[serde(skip_serializing)]
+    #[schema(ignore)]
     pub(crate) pay_by_phone: PayByPhone,
     #[serde(skip)]
     pub(crate) session: Option<Session>,
+    #[serde(skip)]
+    pub(crate) unmanaged: Option<ParkingSession>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub(crate) struct Session {
     #[serde(rename = "nextCheck")]
     pub(crate) next_check: DateTime<Utc>,
     pub(crate) duration: i16,
+    #[serde(default)]
+    pub(crate) paused: bool,
+    #[serde(rename = "dryRun", default)]
+    pub(crate) dry_run: bool,
+    #[serde(skip)]
+    pub(crate) verified: bool,
+    /// Consecutive failures to check the session upstream before renewing it.
+    #[serde(skip)]
+    pub(crate) verify_failures: u8,
+}
+
+impl Session {
+    pub(crate) fn new(expiry: DateTime<Utc>, target: DateTime<Utc>, dry_run: bool) -> Self {
+        let next_check = expiry + chrono::Duration::minutes(1);
+        Self {
+            next_check,
+            duration: (target - next_check).num_minutes() as i16,
+            paused: false,
+            dry_run,
+            verified: false,
+            verify_failures: 0,
+        }
+    }
+
+    /// Whether a plan can run until the target, the minutes left being stored as an i16.
+    pub(crate) fn can_plan(now: DateTime<Utc>, target: DateTime<Utc>) -> bool {
+        target > now && target - now <= chrono::Duration::minutes(i16::MAX as i64)
+    }
+
+    pub(crate) fn target(&self) -> DateTime<Utc> {
+        self.next_check + chrono::Duration::minutes(self.duration as i64)
+    }
+
+    pub(crate) fn expected_expiry(&self) -> DateTime<Utc> {
+        self.next_check - chrono::Duration::minutes(1)
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.duration > 0
+    }
+
+    /// Whether a live session expiring at `expiry` was bought by this plan, i.e. the plan is still
+    /// running or its last purchase expires then. A dry-run plan buys nothing.
+    pub(crate) fn owns(&self, expiry: DateTime<Utc>) -> bool {
+        !self.dry_run
+            && (self.is_active()
+                || (expiry - self.expected_expiry()).abs() <= chrono::Duration::minutes(1))
+    }
+
+    pub(crate) fn reschedule(&mut self, expiry: DateTime<Utc>) {
+        let target = self.target();
+        self.next_check = expiry + chrono::Duration::minutes(1);
+        self.duration = (target - self.next_check).num_minutes() as i16;
+        self.verified = false;
+        self.verify_failures = 0;
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct PayByPhone {
     pub(crate) login: String,
     pub(crate) password: String,
     pub(crate) payment_account_id: String,
 }
 
+impl fmt::Debug for PayByPhone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PayByPhone")
+            .field("login", &self.login)
+            .field("password", &REDACTED)
+            .field("payment_account_id", &REDACTED)
+            .finish()
+    }
+}
+
 pub(crate) fn read(file_path: &str) -> Result<Accounts, Box<dyn std::error::Error>> {
     match fs::read_to_string(file_path) {
         Ok(contents) => match serde_yaml::from_str(&contents) {
@@ -0,0 +1,53 @@
+use crate::config::Session;
+use crate::types::{Cost, Quote, QuoteItem};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Purchase the scheduler will make to keep the car parked until the target.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct PlannedPurchase {
+    start: DateTime<Utc>,
+    expiry: DateTime<Utc>,
+    cost: Cost,
+    items: Vec<QuoteItem>,
+}
+
+/// Cost of parking until a target, renewal included.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Estimate {
+    target: DateTime<Utc>,
+    /// Expiry of the last purchase, at or after the target.
+    covered_until: DateTime<Utc>,
+    purchases: Vec<PlannedPurchase>,
+    total_cost: Cost,
+}
+
+/// Plans the purchases made by the scheduler from now until the target, each priced like the quote
+/// of a single purchase made now.
+pub(crate) fn estimate(quote: &Quote, target: DateTime<Utc>) -> Estimate {
+    let length = quote.parking_expiry_time - quote.parking_start_time;
+    let purchase = |start: DateTime<Utc>| PlannedPurchase {
+        start,
+        expiry: start + length,
+        cost: quote.total_cost.clone(),
+        items: quote.quote_items.clone(),
+    };
+    let mut purchases = vec![purchase(quote.parking_start_time)];
+    // Renewed the way the scheduler does, until the plan is over.
+    let mut session = Session::new(quote.parking_expiry_time, target, false);
+    while session.is_active() {
+        let renewal = purchase(session.next_check);
+        session.reschedule(renewal.expiry);
+        purchases.push(renewal);
+    }
+    Estimate {
+        target,
+        covered_until: session.expected_expiry(),
+        total_cost: Cost {
+            amount: quote.total_cost.amount * purchases.len() as f64,
+            currency: quote.total_cost.currency.clone(),
+        },
+        purchases,
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::{Session, User};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::LazyLock;
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::{BroadcastStream, WatchStream};
+use tokio_stream::{Stream, StreamExt};
+use utoipa::ToSchema;
+
+/// Updates kept for subscribers lagging behind, past which they miss the oldest ones.
+const CAPACITY: usize = 64;
+
+/// Change to an account or to the configuration, pushed to the clients of `/api/events`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Update {
+    SessionStarted {
+        account: String,
+        expiry: DateTime<Utc>,
+        plan: Option<Session>,
+    },
+    SessionRenewed {
+        account: String,
+        expiry: DateTime<Utc>,
+        plan: Session,
+    },
+    RenewalFailed {
+        account: String,
+        error: String,
+    },
+    PlanPaused {
+        account: String,
+        paused: bool,
+    },
+    PlanStopped {
+        account: String,
+        reason: String,
+    },
+    ConfigReloaded,
+}
+
+impl Update {
+    fn account(&self) -> Option<&str> {
+        match self {
+            Update::SessionStarted { account, .. }
+            | Update::SessionRenewed { account, .. }
+            | Update::RenewalFailed { account, .. }
+            | Update::PlanPaused { account, .. }
+            | Update::PlanStopped { account, .. } => Some(account),
+            Update::ConfigReloaded => None,
+        }
+    }
+}
+
+pub(crate) struct Events {
+    sender: broadcast::Sender<Update>,
+    closed: watch::Sender<bool>,
+}
+
+pub(crate) static EVENTS: LazyLock<Events> = LazyLock::new(|| Events {
+    sender: broadcast::channel(CAPACITY).0,
+    closed: watch::Sender::new(false),
+});
+
+impl Events {
+    pub(crate) fn publish(&self, update: Update) {
+        // Sending only fails when nobody is listening.
+        let _ = self.sender.send(update);
+    }
+
+    /// Ends every stream, so that open connections do not hold back the graceful shutdown.
+    pub(crate) fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    /// Streams the updates of the accounts a user has access to, as server-sent events, until the
+    /// events are closed.
+    pub(crate) fn stream(&self, user: User) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        let closed = WatchStream::new(self.closed.subscribe())
+            .filter(|closed| *closed)
+            .map(|_| None);
+        let updates = BroadcastStream::new(self.sender.subscribe())
+            .map(Some)
+            .merge(closed)
+            .take_while(Option::is_some)
+            .filter_map(move |update| match update? {
+                Ok(update) if update.account().is_none_or(|a| user.can_access(a)) => {
+                    Event::default().json_data(&update).ok().map(Ok)
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    log::warn!("Event stream of {} lagged: {:?}", user.name, e);
+                    None
+                }
+            });
+        Sse::new(updates).keep_alive(KeepAlive::default())
+    }
+}
@@ -0,0 +1,37 @@
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Serves the built frontend from a directory, answering the client-side routes with its index.
+pub(crate) fn directory(dir: &str) -> ServeDir<ServeFile> {
+    ServeDir::new(dir).fallback(ServeFile::new(format!("{}/index.html", dir)))
+}
+
+#[cfg(feature = "embed-front")]
+mod embedded {
+    use axum::http::{header, StatusCode, Uri};
+    use axum::response::{IntoResponse, Response};
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[folder = "../target/dx/front/release/web/public/"]
+    #[allow_missing = true]
+    struct Assets;
+
+    /// Serves the frontend embedded at build time, answering the client-side routes with its index.
+    pub(crate) async fn serve(uri: Uri) -> Response {
+        let path = uri.path().trim_start_matches('/');
+        let file = Assets::get(path)
+            .filter(|_| !path.is_empty())
+            .or_else(|| Assets::get("index.html"));
+        match file {
+            Some(file) => (
+                [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+                file.data,
+            )
+                .into_response(),
+            None => (StatusCode::NOT_FOUND, "Frontend not embedded").into_response(),
+        }
+    }
+}
+
+#[cfg(feature = "embed-front")]
+pub(crate) use embedded::serve as embedded;
@@ -0,0 +1,125 @@
+use crate::config::Accounts;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::sync::{LazyLock, Mutex};
+use utoipa::ToSchema;
+
+/// Longest time without a tick of the renewal loop, which ticks every minute, before it is considered stuck.
+const RENEWAL_TICK_TIMEOUT: Duration = Duration::seconds(150);
+
+#[derive(Debug, Default, Clone, Copy, Serialize, ToSchema)]
+pub(crate) struct Check {
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+}
+
+impl Check {
+    /// Whether the last attempt succeeded, if there was any.
+    fn ok(&self) -> Option<bool> {
+        match (self.last_success, self.last_failure) {
+            (None, None) => None,
+            (Some(success), Some(failure)) => Some(success >= failure),
+            (success, _) => Some(success.is_some()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Health {
+    renewal_tick: Mutex<Option<DateTime<Utc>>>,
+    upstream: Mutex<Check>,
+    api_key: Mutex<Check>,
+    store: Mutex<Check>,
+}
+
+pub(crate) static HEALTH: LazyLock<Health> = LazyLock::new(Health::default);
+
+#[derive(Serialize, ToSchema)]
+struct CheckReport {
+    ok: Option<bool>,
+    #[serde(flatten)]
+    check: Check,
+}
+
+impl From<Check> for CheckReport {
+    fn from(check: Check) -> Self {
+        Self {
+            ok: check.ok(),
+            check,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct ConfigReport {
+    ok: bool,
+    accounts: usize,
+    users: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RenewalReport {
+    ok: bool,
+    last_tick: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Readiness {
+    pub(crate) ready: bool,
+    config: ConfigReport,
+    renewal_loop: RenewalReport,
+    upstream: CheckReport,
+    api_key: CheckReport,
+    store: CheckReport,
+}
+
+fn record(check: &Mutex<Check>, success: bool) {
+    let mut check = check.lock().unwrap();
+    if success {
+        check.last_success = Some(Utc::now());
+    } else {
+        check.last_failure = Some(Utc::now());
+    }
+}
+
+impl Health {
+    pub(crate) fn renewal_tick(&self) {
+        *self.renewal_tick.lock().unwrap() = Some(Utc::now());
+    }
+
+    pub(crate) fn upstream(&self, success: bool) {
+        record(&self.upstream, success);
+    }
+
+    pub(crate) fn api_key(&self, success: bool) {
+        record(&self.api_key, success);
+    }
+
+    pub(crate) fn store(&self, success: bool) {
+        record(&self.store, success);
+    }
+
+    /// Reports the state of every component. PayByPhone being unreachable is reported but does not
+    /// make the instance unready, as no other instance could reach it either.
+    pub(crate) fn readiness(&self, accounts: &Accounts) -> Readiness {
+        let last_tick = *self.renewal_tick.lock().unwrap();
+        let renewal_loop = RenewalReport {
+            ok: last_tick.is_some_and(|tick| Utc::now() - tick < RENEWAL_TICK_TIMEOUT),
+            last_tick,
+        };
+        let config = ConfigReport {
+            ok: !accounts.accounts.is_empty(),
+            accounts: accounts.accounts.len(),
+            users: accounts.users.len(),
+        };
+        let store = CheckReport::from(*self.store.lock().unwrap());
+        Readiness {
+            ready: config.ok && renewal_loop.ok && store.ok != Some(false),
+            config,
+            renewal_loop,
+            upstream: CheckReport::from(*self.upstream.lock().unwrap()),
+            api_key: CheckReport::from(*self.api_key.lock().unwrap()),
+            store,
+        }
+    }
+}
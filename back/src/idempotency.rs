@@ -0,0 +1,226 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+enum Entry {
+    InFlight,
+    Done(StatusCode, serde_json::Value),
+}
+
+struct Record {
+    created: DateTime<Utc>,
+    /// Hash of the body of the request.
+    fingerprint: String,
+    entry: Entry,
+}
+
+pub(crate) enum Begin {
+    Started(Pending),
+    InFlight,
+    /// The key was already used for a request with another body.
+    Mismatch,
+    Replay(StatusCode, serde_json::Value),
+}
+
+#[derive(Default)]
+pub(crate) struct Idempotency {
+    entries: Mutex<HashMap<String, Record>>,
+}
+
+/// Request started under a key, forgotten unless finished, e.g. when its task panics.
+pub(crate) struct Pending {
+    idempotency: Arc<Idempotency>,
+    key: String,
+    finished: bool,
+}
+
+impl Pending {
+    /// Keeps the response of the request to replay it.
+    fn finish(mut self, status: StatusCode, body: serde_json::Value) {
+        if let Some(record) = self.idempotency.entries.lock().unwrap().get_mut(&self.key) {
+            record.entry = Entry::Done(status, body);
+        }
+        self.finished = true;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.idempotency.entries.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+impl Idempotency {
+    /// Starts a request under a key, `body` being what the request does, hashed to detect the reuse
+    /// of a key for another request.
+    pub(crate) fn begin(self: &Arc<Self>, key: &str, body: &str) -> Begin {
+        let now = Utc::now();
+        let fingerprint = hex::encode(Sha256::digest(body.as_bytes()));
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, record| record.created + Duration::hours(24) > now);
+        match entries.get(key) {
+            Some(record) if record.fingerprint != fingerprint => Begin::Mismatch,
+            Some(Record {
+                entry: Entry::InFlight,
+                ..
+            }) => Begin::InFlight,
+            Some(Record {
+                entry: Entry::Done(status, body),
+                ..
+            }) => Begin::Replay(*status, body.clone()),
+            None => {
+                entries.insert(
+                    key.to_string(),
+                    Record {
+                        created: now,
+                        fingerprint,
+                        entry: Entry::InFlight,
+                    },
+                );
+                Begin::Started(Pending {
+                    idempotency: self.clone(),
+                    key: key.to_string(),
+                    finished: false,
+                })
+            }
+        }
+    }
+}
+
+/// Runs a request in its own task, so that it completes even if the client disconnects, its key
+/// staying in flight until then. Successful responses are kept to be replayed, and so are 502 Bad
+/// Gateway ones: PayByPhone may have acted on the request, so running it again could pay twice.
+/// Other failures are forgotten, to be retried.
+pub(crate) async fn complete<F>(
+    pending: Option<Pending>,
+    request: F,
+) -> (StatusCode, serde_json::Value)
+where
+    F: Future<Output = (StatusCode, serde_json::Value)> + Send + 'static,
+{
+    let task = tokio::spawn(async move {
+        let (status, body) = request.await;
+        if let Some(pending) = pending {
+            if status.is_success() || status == StatusCode::BAD_GATEWAY {
+                pending.finish(status, body.clone());
+            }
+        }
+        (status, body)
+    });
+    match task.await {
+        Ok(response) => response,
+        Err(e) => {
+            log::error!("Request failed: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                serde_json::Value::from("Internal error"),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::oneshot;
+
+    fn start(idempotency: &Arc<Idempotency>, key: &str, body: &str) -> Pending {
+        match idempotency.begin(key, body) {
+            Begin::Started(pending) => pending,
+            _ => panic!("{} was already started", key),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_successful_response() {
+        let idempotency = Arc::new(Idempotency::default());
+        let pending = start(&idempotency, "key", "car:60");
+        complete(Some(pending), async {
+            (StatusCode::ACCEPTED, json!("paid"))
+        })
+        .await;
+        match idempotency.begin("key", "car:60") {
+            Begin::Replay(status, body) => {
+                assert_eq!(status, StatusCode::ACCEPTED);
+                assert_eq!(body, json!("paid"));
+            }
+            _ => panic!("not replayed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_key_reused_for_another_body() {
+        let idempotency = Arc::new(Idempotency::default());
+        let pending = start(&idempotency, "key", "car:60");
+        assert!(matches!(
+            idempotency.begin("key", "car:90"),
+            Begin::Mismatch
+        ));
+        complete(Some(pending), async {
+            (StatusCode::ACCEPTED, json!("paid"))
+        })
+        .await;
+        assert!(matches!(
+            idempotency.begin("key", "car:90"),
+            Begin::Mismatch
+        ));
+    }
+
+    #[tokio::test]
+    async fn forgets_failure_but_keeps_upstream_failure() {
+        let idempotency = Arc::new(Idempotency::default());
+        let pending = start(&idempotency, "failed", "car:60");
+        complete(Some(pending), async {
+            (StatusCode::INTERNAL_SERVER_ERROR, json!("login failed"))
+        })
+        .await;
+        start(&idempotency, "failed", "car:60");
+
+        let pending = start(&idempotency, "unconfirmed", "car:60");
+        complete(Some(pending), async {
+            (StatusCode::BAD_GATEWAY, json!("may have been charged"))
+        })
+        .await;
+        assert!(matches!(
+            idempotency.begin("unconfirmed", "car:60"),
+            Begin::Replay(StatusCode::BAD_GATEWAY, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn completes_request_dropped_by_the_client() {
+        let idempotency = Arc::new(Idempotency::default());
+        let pending = start(&idempotency, "key", "car:60");
+        let (pay, paid) = oneshot::channel::<()>();
+        let request = complete(Some(pending), async move {
+            paid.await.ok();
+            (StatusCode::ACCEPTED, json!("paid"))
+        });
+        // The client disconnects while the purchase is in progress.
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(10), request)
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            idempotency.begin("key", "car:60"),
+            Begin::InFlight
+        ));
+
+        pay.send(()).unwrap();
+        for _ in 0..100 {
+            if let Begin::Replay(status, _) = idempotency.begin("key", "car:60") {
+                assert_eq!(status, StatusCode::ACCEPTED);
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+        panic!("the purchase was not completed");
+    }
+}
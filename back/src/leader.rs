@@ -0,0 +1,136 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Holder {
+    holder: String,
+    heartbeat: DateTime<Utc>,
+}
+
+pub(crate) struct Lease {
+    path: PathBuf,
+    holder: String,
+    ttl: Duration,
+    leader: AtomicBool,
+}
+
+impl Lease {
+    pub(crate) fn new(path: impl Into<PathBuf>, ttl: i64) -> Self {
+        let hostname = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| fs::read_to_string("/etc/hostname").ok())
+            .map(|h| h.trim().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Self {
+            path: path.into(),
+            holder: format!("{}:{}", hostname, std::process::id()),
+            ttl: Duration::seconds(ttl),
+            leader: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    fn read(&self) -> Result<Option<Holder>, Box<dyn std::error::Error + Send + Sync>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(holder) => Ok(Some(holder)),
+                Err(e) => Err(Box::new(e)),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn write(&self, now: DateTime<Utc>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let tmp = self
+            .path
+            .with_extension(format!("lock.{}.tmp", std::process::id()));
+        fs::write(
+            &tmp,
+            serde_json::to_string(&Holder {
+                holder: self.holder.clone(),
+                heartbeat: now,
+            })?,
+        )?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Locks the file guarding the lease, so that two instances never both read an expired lease
+    /// and take it over. The lock is released when the returned file is dropped, or the process
+    /// dies.
+    fn exclusive(&self) -> std::io::Result<File> {
+        let guard = File::create(self.path.with_extension("lock.guard"))?;
+        guard.lock()?;
+        Ok(guard)
+    }
+
+    /// Acquires or refreshes the lease, and returns whether this instance is now the leader.
+    pub(crate) fn heartbeat(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let _guard = self.exclusive()?;
+        let leader = match self.read()? {
+            Some(current) if current.holder == self.holder => {
+                self.write(now)?;
+                true
+            }
+            Some(current) if current.heartbeat + self.ttl > now => false,
+            current => {
+                if let Some(current) = current {
+                    log::warn!(
+                        "Lease of {} expired at {}, taking over",
+                        current.holder,
+                        current.heartbeat + self.ttl
+                    );
+                }
+                self.write(now)?;
+                true
+            }
+        };
+
+        if leader != self.leader.swap(leader, Ordering::SeqCst) {
+            if leader {
+                log::info!("{} is now the leader", self.holder);
+            } else {
+                log::info!("{} is no longer the leader", self.holder);
+            }
+        }
+        Ok(leader)
+    }
+
+    pub(crate) fn release(&self) {
+        if !self.is_leader() {
+            return;
+        }
+        let _guard = match self.exclusive() {
+            Ok(guard) => guard,
+            Err(e) => {
+                log::error!("Failed to release lease: {:?}", e);
+                return;
+            }
+        };
+        match self.read() {
+            Ok(Some(current)) if current.holder == self.holder => {
+                if let Err(e) = fs::remove_file(&self.path) {
+                    log::error!("Failed to release lease: {:?}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("Failed to release lease: {:?}", e),
+        }
+        self.leader.store(false, Ordering::SeqCst);
+    }
+}
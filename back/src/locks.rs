@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+#[derive(Default)]
+pub(crate) struct Locks {
+    accounts: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Locks {
+    /// Locks the purchase operations of an account, or returns None if one is already running.
+    pub(crate) fn try_lock(&self, account_name: &str) -> Option<OwnedMutexGuard<()>> {
+        self.accounts
+            .lock()
+            .unwrap()
+            .entry(account_name.to_string())
+            .or_default()
+            .clone()
+            .try_lock_owned()
+            .ok()
+    }
+}
@@ -0,0 +1,71 @@
+use clap::ValueEnum;
+use serde_json::Value;
+use tracing_subscriber::EnvFilter;
+
+/// Placeholder logged instead of a secret.
+pub(crate) const REDACTED: &str = "[redacted]";
+
+/// Keys whose values are never logged, compared without case, dashes nor underscores.
+const SECRET_KEYS: [&str; 11] = [
+    "password",
+    "token",
+    "accesstoken",
+    "refreshtoken",
+    "apikey",
+    "xapikey",
+    "authorization",
+    "cookie",
+    "paymentaccountid",
+    "tokenhash",
+    "passwordhash",
+];
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Installs the global subscriber, filtered by RUST_LOG (info by default). Records of the `log`
+/// macros are forwarded to it, so they carry the request and renewal spans they are emitted in.
+pub(crate) fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_lowercase().replace(['-', '_'], "");
+    SECRET_KEYS.contains(&key.as_str())
+}
+
+/// Returns a copy of a JSON value with the values of every secret key replaced, at any depth.
+pub(crate) fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    if is_secret(key) {
+                        (key.clone(), Value::from(REDACTED))
+                    } else {
+                        (key.clone(), redact(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redact).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Generates an identifier correlating the log lines of a renewal.
+pub(crate) fn correlation_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
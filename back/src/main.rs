@@ -1,25 +1,83 @@
+mod audit;
+mod clock;
 mod config;
+mod estimate;
+mod events;
+mod front;
+mod health;
+mod idempotency;
+mod jwt;
+mod leader;
+mod locks;
+mod logging;
+mod metrics;
 mod middleware;
+mod openapi;
 mod paybyphone;
+mod ratelimit;
+mod scheduler;
+mod sessions;
+mod simulation;
+mod state;
+mod status;
+mod tls;
 mod types;
+mod v1;
 
-use crate::config::{Accounts, Session};
-use crate::middleware::auth_middleware;
-use axum::extract::{Query, State};
-use axum::middleware::from_fn;
-use axum::response::IntoResponse;
-use axum::{
-    http::StatusCode,
-    routing::{get, post},
-    Json, Router,
+use crate::audit::{AuditLog, Event, Filter};
+use crate::clock::SystemClock;
+use crate::config::{Accounts, Role, Session, User};
+use crate::events::{Update, EVENTS};
+use crate::health::HEALTH;
+use crate::idempotency::{complete, Begin, Idempotency};
+use crate::jwt::JwtValidator;
+use crate::leader::Lease;
+use crate::locks::Locks;
+use crate::logging::LogFormat;
+use crate::metrics::METRICS;
+use crate::middleware::{
+    auth_middleware, authorize, cors_layer, deprecation, hash_token, leader_middleware,
+    request_span,
+};
+use crate::openapi::ApiDoc;
+use crate::paybyphone::Unconfirmed;
+use crate::ratelimit::{lockout, rate_limit, upstream_limit, Peer, RateLimits};
+use crate::scheduler::{check_renewal, PayByPhoneProvider};
+use crate::sessions::{
+    cookie, hash_password, verify_password, Sessions, REFRESH_COOKIE, SESSION_COOKIE,
 };
-use clap::Parser;
+use crate::state::Store;
+use crate::status::StatusCache;
+use crate::tls::{Tls, TlsListener};
+use axum::extract::connect_info::Connected;
+use axum::extract::{Extension, FromRef, Query, State};
+use axum::http::HeaderMap;
+use axum::middleware::{from_fn, from_fn_with_state, map_response};
+use axum::response::IntoResponse;
+use axum::serve::{IncomingStream, Listener};
+use axum::{http::StatusCode, routing::get, Json, Router};
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::error::Error;
+use std::net::IpAddr;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
 use tokio::time::{sleep_until, Instant};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_axum::router::{OpenApiRouter, UtoipaMethodRouterExt};
+use utoipa_axum::routes;
+use utoipa_swagger_ui::SwaggerUi;
 
 #[derive(Parser, Debug)]
 #[command(version = "0.1.0", author = "Rémi Espié", about, long_about = None)]
@@ -28,109 +86,640 @@ struct Args {
     #[arg(short, long, default_value = "3000")]
     port: u16,
 
-    /// Bearer token for authentication. Can be set through the BEARER environment variable.
+    /// The address the application will listen on. Can be set through the BIND environment variable. Default is 0.0.0.0.
+    #[arg(long, env, default_value = "0.0.0.0")]
+    bind: IpAddr,
+
+    /// PEM certificate chain to serve HTTPS with, reloaded when it changes. Can be set through the TLS_CERT environment variable.
+    #[arg(long, env, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM private key of the certificate. Can be set through the TLS_KEY environment variable.
+    #[arg(long, env, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// Unix socket to listen on instead of the TCP port. Can be set through the UNIX_SOCKET environment variable.
+    #[cfg(unix)]
+    #[arg(long, env, conflicts_with_all = ["tls_cert", "bind"])]
+    unix_socket: Option<String>,
+
+    /// Bearer token granting admin access to every account. Can be set through the BEARER environment variable.
     #[arg(short, long, env)]
-    bearer: String,
+    bearer: Option<String>,
+
+    /// Quote parkings and renewals without paying for them. Can be set through the DRY_RUN environment variable.
+    #[arg(long, env)]
+    dry_run: bool,
+
+    /// File where the renewal state is persisted. Can be set through the STATE environment variable.
+    #[arg(short, long, env, default_value = "state.json")]
+    state: String,
+
+    /// Seconds to wait for in-flight parkings and renewals on shutdown. Default is 30.
+    #[arg(long, env, default_value = "30")]
+    shutdown_timeout: u64,
+
+    /// Seconds after which the renewal lease of a silent leader can be taken over. Default is 180.
+    #[arg(long, env, default_value = "180")]
+    lease_ttl: i64,
+
+    /// File where the audit log of every action is appended. Can be set through the AUDIT_LOG environment variable.
+    #[arg(long, env, default_value = "audit.jsonl")]
+    audit_log: String,
+
+    /// Bearer token required to scrape /metrics. Can be set through the METRICS_BEARER environment variable.
+    #[arg(long, env)]
+    metrics_bearer: Option<String>,
+
+    /// Only send the session cookies over HTTPS. Can be set through the SECURE_COOKIES environment variable.
+    #[arg(long, env)]
+    secure_cookies: bool,
+
+    /// Requests per minute allowed to each user. Can be set through the RATE_LIMIT environment variable. Default is 120, 0 for no limit.
+    #[arg(long, env, default_value = "120")]
+    rate_limit: u32,
+
+    /// Requests per minute allowed to each user on the routes calling PayByPhone. Can be set through the UPSTREAM_RATE_LIMIT environment variable. Default is 10, 0 for no limit.
+    #[arg(long, env, default_value = "10")]
+    upstream_rate_limit: u32,
+
+    /// Format of the logs, text or json. Can be set through the LOG_FORMAT environment variable.
+    #[arg(long, env, value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Directory of the built frontend to serve at /. Can be set through the FRONT_DIR environment variable.
+    #[arg(long, env)]
+    front_dir: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Simulate the renewal of the configured accounts against a fake provider on a virtual clock.
+    Simulate {
+        /// Only simulate this account. Default is every configured account.
+        #[arg(short, long)]
+        account: Option<String>,
+
+        /// Duration of the parking plan, in minutes. Default is 12 hours.
+        #[arg(short, long, default_value = "720")]
+        duration: i16,
+
+        /// Number of virtual days to simulate. Default is 7.
+        #[arg(long, default_value = "7")]
+        days: i64,
+
+        /// Duration of a single purchase, in minutes. Default is 15.
+        #[arg(short, long, default_value = "15")]
+        unit: i64,
+
+        /// Cost of a single purchase. Default is 0.5.
+        #[arg(short, long, default_value = "0.5")]
+        cost: f64,
+    },
+    /// Print the hash of a token, to be set as the token_hash of a user in config.yaml.
+    HashToken {
+        /// The token to hash.
+        token: String,
+    },
+    /// Print the hash of a password, to be set as the password_hash of a user in config.yaml.
+    HashPassword {
+        /// The password to hash.
+        password: String,
+    },
+    /// Print a JWT signed with a local RSA key for the issuer and audience of config.yaml.
+    SignJwt {
+        /// Path of the PEM encoded RSA private key.
+        #[arg(short, long)]
+        key: String,
+
+        /// Key ID of the signing key in the JWKS. Default is "local".
+        #[arg(long, default_value = "local")]
+        kid: String,
+
+        /// Subject of the token, matched against the configured users.
+        subject: String,
+
+        /// Role granted by the token, when a role claim is configured.
+        #[arg(short, long)]
+        role: Option<String>,
+
+        /// Accounts granted by the token, when an accounts claim is configured.
+        #[arg(short, long)]
+        accounts: Vec<String>,
+
+        /// Validity of the token, in seconds. Default is 1 hour.
+        #[arg(long, default_value = "3600")]
+        ttl: i64,
+    },
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<RwLock<Accounts>>,
+    locks: Arc<Locks>,
+    idempotency: Arc<Idempotency>,
+    sessions: Arc<Sessions>,
+    audit: Arc<AuditLog>,
+    status: Arc<StatusCache>,
+    store: Arc<Store>,
+}
+
+impl FromRef<AppState> for Arc<RwLock<Accounts>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Locks> {
+    fn from_ref(state: &AppState) -> Self {
+        state.locks.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Idempotency> {
+    fn from_ref(state: &AppState) -> Self {
+        state.idempotency.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Sessions> {
+    fn from_ref(state: &AppState) -> Self {
+        state.sessions.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<AuditLog> {
+    fn from_ref(state: &AppState) -> Self {
+        state.audit.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<StatusCache> {
+    fn from_ref(state: &AppState) -> Self {
+        state.status.clone()
+    }
 }
 
-#[derive(Deserialize)]
+impl FromRef<AppState> for Arc<Store> {
+    fn from_ref(state: &AppState) -> Self {
+        state.store.clone()
+    }
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct AccountName {
     name: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct SessionId {
+    id: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct Adoption {
+    name: String,
+    end: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Unmanaged {
+    name: String,
+    session: types::ParkingSession,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct Parking {
     name: String,
     duration: i16,
+    #[serde(default)]
+    dry_run: bool,
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
     dotenv().ok();
 
     let args = Args::parse();
-    let bearer_token = Arc::new(args.bearer.clone());
+    logging::init(args.log_format);
+
+    match &args.command {
+        Some(Command::HashToken { token }) => {
+            println!("{}", hash_token(token));
+            return;
+        }
+        Some(Command::HashPassword { password }) => {
+            match hash_password(password) {
+                Ok(hash) => println!("{}", hash),
+                Err(e) => log::error!("{:?}", e),
+            }
+            return;
+        }
+        _ => {}
+    }
 
     log::info!("Reading user config...");
-    let config = Arc::new(RwLock::new(
-        config::read("config.yaml").unwrap_or_else(|e| panic!("{:?}", e)),
+    let mut accounts = config::read("config.yaml").unwrap_or_else(|e| panic!("{:?}", e));
+
+    if let Some(Command::Simulate {
+        account,
+        duration,
+        days,
+        unit,
+        cost,
+    }) = args.command
+    {
+        if let Err(e) = simulation::run(accounts, account, duration, days, unit, cost).await {
+            log::error!("{:?}", e);
+        }
+        return;
+    }
+
+    if let Some(Command::SignJwt {
+        key,
+        kid,
+        subject,
+        role,
+        accounts: granted,
+        ttl,
+    }) = args.command
+    {
+        match &accounts.oidc {
+            Some(oidc) => match jwt::sign(oidc, &key, kid, subject, role, granted, ttl) {
+                Ok(token) => println!("{}", token),
+                Err(e) => log::error!("{:?}", e),
+            },
+            None => log::error!("No oidc section in config.yaml"),
+        }
+        return;
+    }
+
+    if args.bearer.is_none() && accounts.users.is_empty() && accounts.oidc.is_none() {
+        panic!("No BEARER token, users nor OIDC configured, nobody could use the API");
+    }
+    let bearer_token = Arc::new(args.bearer.clone());
+    let jwt = Arc::new(accounts.oidc.clone().map(JwtValidator::new));
+    if args.dry_run {
+        accounts.settings.dry_run = true;
+    }
+    if accounts.settings.dry_run {
+        log::warn!("Dry run enabled, no parking will be paid");
+    }
+    let store = Arc::new(Store::new(args.state.clone()));
+    store
+        .restore(&mut accounts)
+        .unwrap_or_else(|e| panic!("{:?}", e));
+    let config = Arc::new(RwLock::new(accounts));
+    let (shutdown_sender, shutdown) = watch::channel(false);
+
+    let lease = Arc::new(Lease::new(format!("{}.lock", args.state), args.lease_ttl));
+    match lease.heartbeat(chrono::Utc::now()) {
+        Ok(true) => {}
+        Ok(false) => log::info!("Another instance is the leader, serving read-only requests"),
+        Err(e) => log::error!("Failed to acquire lease: {:?}", e),
+    }
+    let leader_lease = lease.clone();
+    let auth_config = config.clone();
+    let locks = Arc::new(Locks::default());
+    let sessions = Arc::new(Sessions::new(
+        args.secure_cookies || args.tls_cert.is_some(),
     ));
+    let auth_sessions = sessions.clone();
+    let audit = Arc::new(AuditLog::new(args.audit_log.clone()));
+    let limits = Arc::new(RateLimits::new(args.rate_limit, args.upstream_rate_limit));
+    let auth_limits = limits.clone();
+    let state = AppState {
+        config: config.clone(),
+        locks: locks.clone(),
+        idempotency: Arc::new(Idempotency::default()),
+        sessions,
+        audit: audit.clone(),
+        status: Arc::new(StatusCache::default()),
+        store: store.clone(),
+    };
 
-    let nested = Router::new()
-        .route("/healthz", get(()))
-        .route("/accounts", get(get_accounts))
-        .route("/quote", get(get_quote))
-        .route("/park", post(park))
-        .route("/check", get(get_sessions))
-        .route("/check_renew", get(check_renew))
-        .route("/vehicles", get(get_vehicles))
-        .with_state(config.clone())
+    // Superseded by the routes of /api/v1/accounts/{name}, kept for existing clients.
+    let upstream = from_fn_with_state(limits.clone(), upstream_limit);
+    let legacy = openapi::deprecate(
+        OpenApiRouter::new()
+            .routes(routes!(get_accounts))
+            .routes(routes!(get_quote).layer(upstream.clone()))
+            .routes(routes!(park).layer(upstream.clone()))
+            .routes(routes!(get_sessions).layer(upstream.clone()))
+            .routes(routes!(check_renew))
+            .routes(routes!(pause))
+            .routes(routes!(resume))
+            .routes(routes!(adopt).layer(upstream.clone()))
+            .routes(routes!(get_vehicles).layer(upstream))
+            .layer(map_response(deprecation)),
+    );
+    let nested = OpenApiRouter::new()
+        .routes(routes!(healthz))
+        .routes(routes!(whoami))
+        .routes(routes!(get_status))
+        .routes(routes!(get_events))
+        .routes(routes!(reload_config))
+        .routes(routes!(get_unmanaged))
+        .routes(routes!(get_login_sessions))
+        .routes(routes!(revoke_login_session))
+        .routes(routes!(get_audit))
+        .merge(legacy)
+        .nest("/v1", v1::router(&limits))
+        .fallback(|| async { (StatusCode::NOT_FOUND, Json("Not found")) })
+        .with_state(state.clone())
+        .layer(from_fn_with_state(limits.clone(), rate_limit))
         .layer(from_fn(move |req, next| {
-            auth_middleware(req, next, bearer_token.clone())
-        }));
+            leader_middleware(req, next, leader_lease.clone())
+        }))
+        .layer(from_fn(move |req, next| {
+            auth_middleware(
+                req,
+                next,
+                bearer_token.clone(),
+                auth_config.clone(),
+                auth_sessions.clone(),
+                jwt.clone(),
+                auth_limits.clone(),
+            )
+        }))
+        .layer(from_fn(v1::error_envelope))
+        .merge(
+            OpenApiRouter::new()
+                .routes(routes!(login).layer(from_fn_with_state(limits, lockout)))
+                .routes(routes!(refresh))
+                .routes(routes!(logout))
+                .with_state(state),
+        );
+    // Documented paths are prefixed with /api, like the routes.
+    let (nested, api_doc) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .nest("/api", nested)
+        .split_for_parts();
+    let nested = nested.route_layer(from_fn(audit::route));
 
-    let app = Router::new().nest("/api", nested);
+    let nested = match &config.read().await.cors {
+        Some(cors) => nested.layer(cors_layer(cors).unwrap_or_else(|e| panic!("{:?}", e))),
+        None => nested,
+    };
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", args.port))
-        .await
-        .unwrap();
-    log::info!("Listening on 0.0.0.0:{}", args.port);
-    tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
+    let metrics_config = config.clone();
+    let metrics_bearer = args.metrics_bearer.clone();
+    let readiness_config = config.clone();
+    let app = Router::new()
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", api_doc))
+        .merge(nested)
+        .route("/livez", get(livez))
+        .route(
+            "/readyz",
+            get(move || get_readiness(readiness_config.clone())),
+        )
+        .route(
+            "/metrics",
+            get(move |headers: HeaderMap| {
+                get_metrics(metrics_config.clone(), metrics_bearer.clone(), headers)
+            }),
+        );
+
+    let app = match &args.front_dir {
+        Some(dir) => app.fallback_service(front::directory(dir)),
+        #[cfg(feature = "embed-front")]
+        None => app.fallback(front::embedded),
+        #[cfg(not(feature = "embed-front"))]
+        None => app,
+    };
+    let app = app
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+
+    let server = listen(&args, app, shutdown.clone()).await;
+    let discovery_config = config.clone();
+    let discovery = tokio::spawn(async move {
+        let interval = discovery_config.read().await.settings.discovery_interval;
+        if interval == 0 {
+            log::info!("Session discovery disabled");
+            return;
+        }
+        log::info!("Starting discovery loop");
+        let interval = tokio::time::Duration::from_secs(interval * 60);
+        let mut next_discovery = Instant::now();
+        loop {
+            sleep_until(next_discovery).await;
+            next_discovery += interval;
+            // Run on every instance, followers comparing with the plans restored from the store,
+            // since the unmanaged sessions are not persisted.
+            discover_sessions(discovery_config.clone()).await;
+        }
     });
     log::info!("Starting renewal loop");
-    tokio::spawn(async move {
+    let renewal_config = config.clone();
+    let renewal_store = store.clone();
+    let renewal_lease = lease.clone();
+    let mut renewal_shutdown = shutdown.clone();
+    let renewal = tokio::spawn(async move {
         let mut next_check = Instant::now();
         loop {
-            sleep_until(next_check).await;
+            tokio::select! {
+                _ = sleep_until(next_check) => {}
+                _ = renewal_shutdown.wait_for(|stop| *stop) => break,
+            }
             log::debug!("checking renewal at {:?}", chrono::Utc::now());
             next_check += tokio::time::Duration::from_secs(60);
-            check_renewal(config.clone()).await;
+            HEALTH.renewal_tick();
+            match renewal_lease.heartbeat(chrono::Utc::now()) {
+                Ok(true) => {
+                    check_renewal(
+                        renewal_config.clone(),
+                        &locks,
+                        &PayByPhoneProvider {
+                            lease: renewal_lease.clone(),
+                        },
+                        &SystemClock,
+                        &audit,
+                    )
+                    .await;
+                    persist(&renewal_store, &*renewal_config.read().await);
+                }
+                Ok(false) => {
+                    let restored = renewal_store.restore(&mut *renewal_config.write().await);
+                    HEALTH.store(restored.is_ok());
+                    if let Err(e) = restored {
+                        log::error!("Failed to reload state: {:?}", e);
+                    }
+                }
+                Err(e) => log::error!("Failed to refresh lease: {:?}", e),
+            }
         }
+    });
+
+    shutdown_signal().await;
+    log::info!("Shutting down, waiting for in-flight parkings and renewals...");
+    shutdown_sender.send(true).ok();
+    EVENTS.close();
+    discovery.abort();
+
+    let timeout = tokio::time::Duration::from_secs(args.shutdown_timeout);
+    if tokio::time::timeout(timeout, async {
+        let _ = tokio::join!(server, renewal);
     })
     .await
-    .unwrap();
+    .is_err()
+    {
+        log::warn!(
+            "In-flight parkings and renewals did not finish within {} seconds",
+            args.shutdown_timeout
+        );
+    }
+
+    let accounts = config.read().await;
+    if lease.is_leader() {
+        match store.save(&accounts) {
+            Ok(_) => log::info!("State persisted to {}", args.state),
+            Err(e) => log::error!("Failed to persist state: {:?}", e),
+        }
+        lease.release();
+    }
+    for account in accounts.accounts.iter() {
+        if let Some(session) = account.session.as_ref().filter(|s| s.is_active()) {
+            log::info!(
+                "Session of account {} left pending, next check at {} for {} minutes",
+                account.name,
+                session.next_check,
+                session.duration
+            );
+        }
+    }
+}
+
+/// Serves the app on the Unix socket, or on the TCP port over HTTPS or HTTP, until shutdown.
+async fn listen(args: &Args, app: Router, shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+    #[cfg(unix)]
+    if let Some(path) = &args.unix_socket {
+        // Left behind by a previous run.
+        if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path).unwrap_or_else(|e| panic!("{:?}", e));
+        }
+        let listener = UnixListener::bind(path).unwrap_or_else(|e| panic!("{:?}", e));
+        log::info!("Listening on unix:{}", path);
+        return tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(wait_for_shutdown(shutdown))
+                .await
+                .unwrap();
+        });
+    }
+    let listener = TcpListener::bind((args.bind, args.port))
+        .await
+        .unwrap_or_else(|e| panic!("{:?}", e));
+    let address = listener.local_addr().unwrap();
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls = Tls::new(cert, key).unwrap_or_else(|e| panic!("{:?}", e));
+            tls.watch();
+            log::info!("Listening on https://{}", address);
+            serve(TlsListener::new(listener, tls).unwrap(), app, shutdown)
+        }
+        _ => {
+            log::info!("Listening on http://{}", address);
+            serve(listener, app, shutdown)
+        }
+    }
+}
+
+fn serve<L>(listener: L, app: Router, shutdown: watch::Receiver<bool>) -> JoinHandle<()>
+where
+    L: Listener,
+    L::Addr: std::fmt::Debug,
+    Peer: for<'a> Connected<IncomingStream<'a, L>>,
+{
+    tokio::spawn(async move {
+        // The address of the client is needed to lock it out.
+        axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
+            .with_graceful_shutdown(wait_for_shutdown(shutdown))
+            .await
+            .unwrap();
+    })
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+async fn wait_for_shutdown(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
 }
 
 async fn initalize_pay_by_phone(
     config: Arc<RwLock<Accounts>>,
     account_name: String,
 ) -> Result<paybyphone::PayByPhone, Box<dyn Error + Send + Sync>> {
-    match config
+    let account = config
         .read()
         .await
         .accounts
         .iter()
         .find(|a| a.name == account_name)
-    {
-        Some(account) => {
-            let mut pay_by_phone = paybyphone::PayByPhone::new(
-                account.plate.clone(),
-                account.lot,
-                account.pay_by_phone.login.clone(),
-                account.pay_by_phone.password.clone(),
-                account.pay_by_phone.payment_account_id.clone(),
-            );
-            match pay_by_phone.init().await {
-                Ok(_) => {
-                    log::info!("PayByPhone initialized");
-                    Ok(pay_by_phone)
-                }
-                Err(e) => {
-                    log::error!("{:?}", e);
-                    Err(Box::from(format!("Failed to initialize PayByPhone: {}", e)))
-                }
-            }
-        }
+        .cloned();
+    match account {
+        Some(account) => paybyphone::PayByPhone::connect(&account).await,
         None => Err(Box::from("Account not found")),
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/check",
+    tag = "parking",
+    params(AccountName),
+    responses(
+        (status = 200, description = "The live PayByPhone session of the account", body = types::ParkingSession),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 500, description = "No active session, or PayByPhone failed", body = String),
+    )
+)]
 async fn get_sessions(
     State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
     Query(account_name): Query<AccountName>,
 ) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Viewer, Some(&account_name.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
     match initalize_pay_by_phone(config, account_name.name).await {
         Ok(pay_by_phone) => {
             log::info!("Checking...");
@@ -150,10 +739,26 @@ async fn get_sessions(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/check_renew",
+    tag = "renewal",
+    params(AccountName),
+    responses(
+        (status = 200, description = "The renewal plan of the account", body = Session),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 404, description = "No renewal plan", body = String),
+    )
+)]
 async fn check_renew(
     State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
     Query(account_name): Query<AccountName>,
 ) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Viewer, Some(&account_name.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
     match config
         .read()
         .await
@@ -169,10 +774,288 @@ async fn check_renew(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/pause",
+    tag = "renewal",
+    request_body = AccountName,
+    responses(
+        (status = 200, description = "The paused renewal plan", body = Session),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 404, description = "No renewal plan", body = String),
+    )
+)]
+async fn pause(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(store): State<Arc<Store>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Json(account_name): Json<AccountName>,
+) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Parker, Some(&account_name.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
+    let event = Event::new(&user.name, "pause", Some(&account_name.name), json!({}));
+    let response = set_paused(config, &store, account_name.name, true).await;
+    audit.record(record_status(event, response.status(), "Renewal paused"));
+    response
+}
+
+#[utoipa::path(
+    post,
+    path = "/resume",
+    tag = "renewal",
+    request_body = AccountName,
+    responses(
+        (status = 200, description = "The resumed renewal plan", body = Session),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 404, description = "No renewal plan", body = String),
+    )
+)]
+async fn resume(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(store): State<Arc<Store>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Json(account_name): Json<AccountName>,
+) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Parker, Some(&account_name.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
+    let event = Event::new(&user.name, "resume", Some(&account_name.name), json!({}));
+    let response = set_paused(config, &store, account_name.name, false).await;
+    audit.record(record_status(event, response.status(), "Renewal resumed"));
+    response
+}
+
+async fn set_paused(
+    config: Arc<RwLock<Accounts>>,
+    store: &Store,
+    account_name: String,
+    paused: bool,
+) -> axum::response::Response {
+    let mut accounts = config.write().await;
+    let response = match accounts
+        .accounts
+        .iter_mut()
+        .find(|a| a.name == account_name)
+    {
+        Some(conf) => match &mut conf.session {
+            Some(session) => {
+                session.paused = paused;
+                if paused {
+                    log::info!("Renewal paused for account {}", conf.name);
+                } else {
+                    log::info!("Renewal resumed for account {}", conf.name);
+                }
+                EVENTS.publish(Update::PlanPaused {
+                    account: conf.name.clone(),
+                    paused,
+                });
+                (StatusCode::OK, Json(session.clone())).into_response()
+            }
+            None => (StatusCode::NOT_FOUND, Json("No session found")).into_response(),
+        },
+        None => (StatusCode::BAD_REQUEST, Json("No session found")).into_response(),
+    };
+    if response.status().is_success() {
+        persist(store, &accounts);
+    }
+    response
+}
+
+/// Saves the renewal plans as soon as they change, so that a plan is not lost if the leader stops
+/// before its next renewal tick.
+fn persist(store: &Store, accounts: &Accounts) {
+    let saved = store.save(accounts);
+    HEALTH.store(saved.is_ok());
+    if let Err(e) = saved {
+        log::error!("Failed to persist state: {:?}", e);
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/unmanaged",
+    tag = "renewal",
+    responses(
+        (status = 200, description = "Sessions started outside of the app", body = Vec<Unmanaged>),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn get_unmanaged(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    let unmanaged: Vec<Unmanaged> = config
+        .read()
+        .await
+        .accounts
+        .iter()
+        .filter(|a| user.can_access(&a.name))
+        .filter_map(|a| {
+            a.unmanaged.clone().map(|session| Unmanaged {
+                name: a.name.clone(),
+                session,
+            })
+        })
+        .collect();
+    (StatusCode::OK, Json(unmanaged)).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/adopt",
+    tag = "renewal",
+    request_body = Adoption,
+    responses(
+        (status = 200, description = "The renewal plan attached to the session", body = Session),
+        (status = 400, description = "The end time is out of range, or already covered by the session", body = String),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 404, description = "No active session", body = String),
+        (status = 409, description = "Another operation is in progress for the account", body = String),
+        (status = 500, description = "PayByPhone failed", body = String),
+    )
+)]
+async fn adopt(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(store): State<Arc<Store>>,
+    State(locks): State<Arc<Locks>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Json(adoption): Json<Adoption>,
+) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Parker, Some(&adoption.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
+    if !Session::can_plan(chrono::Utc::now(), adoption.end) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(format!("Cannot park until {}", adoption.end)),
+        )
+            .into_response();
+    }
+    let event = Event::new(
+        &user.name,
+        "adopt",
+        Some(&adoption.name),
+        json!({ "end": adoption.end }),
+    );
+    let response = adopt_session(config, &store, &locks, adoption).await;
+    audit.record(record_status(event, response.status(), "Session adopted"));
+    response
+}
+
+/// Completes an audit event from the status of the response.
+fn record_status(event: Event, status: StatusCode, outcome: &str) -> Event {
+    if status.is_success() {
+        event.succeeded(outcome)
+    } else {
+        event.failed(status.to_string())
+    }
+}
+
+async fn adopt_session(
+    config: Arc<RwLock<Accounts>>,
+    store: &Store,
+    locks: &Locks,
+    adoption: Adoption,
+) -> axum::response::Response {
+    let Some(_guard) = locks.try_lock(&adoption.name) else {
+        let (status, body) = busy(&adoption.name);
+        return (status, Json(body)).into_response();
+    };
+    match initalize_pay_by_phone(config.clone(), adoption.name.clone()).await {
+        Ok(pay_by_phone) => {
+            log::info!("Adopting session...");
+            match pay_by_phone.current_session().await {
+                Ok(Some(live)) => {
+                    match chrono::DateTime::parse_from_rfc3339(live.expire_time.as_str()) {
+                        Ok(expiry) => {
+                            let next_check =
+                                expiry.with_timezone(&chrono::Utc) + chrono::Duration::minutes(1);
+                            if adoption.end <= next_check {
+                                return (
+                                    StatusCode::BAD_REQUEST,
+                                    Json("Session already covers the end time"),
+                                )
+                                    .into_response();
+                            }
+                            let session = Session::new(
+                                next_check - chrono::Duration::minutes(1),
+                                adoption.end,
+                                false,
+                            );
+                            let mut accounts = config.write().await;
+                            match accounts
+                                .accounts
+                                .iter_mut()
+                                .find(|a| a.name == adoption.name)
+                            {
+                                Some(conf) => {
+                                    conf.session = Some(session.clone());
+                                    conf.unmanaged = None;
+                                    log::info!("Session of account {} adopted", conf.name);
+                                    EVENTS.publish(Update::SessionStarted {
+                                        account: conf.name.clone(),
+                                        expiry: session.expected_expiry(),
+                                        plan: Some(session.clone()),
+                                    });
+                                    persist(store, &accounts);
+                                    (StatusCode::OK, Json(session)).into_response()
+                                }
+                                None => (StatusCode::BAD_REQUEST, Json("Account not found"))
+                                    .into_response(),
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("{:?}", e);
+                            (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response()
+                        }
+                    }
+                }
+                Ok(None) => (
+                    StatusCode::NOT_FOUND,
+                    Json("No active parking session found"),
+                )
+                    .into_response(),
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response()
+                }
+            }
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(format!("Failed to initialize PayByPhone: {}", e)),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/vehicles",
+    tag = "accounts",
+    params(AccountName),
+    responses(
+        (status = 200, description = "The vehicles of the PayByPhone account", body = Vec<types::Vehicle>),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 500, description = "PayByPhone failed", body = String),
+    )
+)]
 async fn get_vehicles(
     State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
     Query(account_name): Query<AccountName>,
 ) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Viewer, Some(&account_name.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
     match initalize_pay_by_phone(config, account_name.name).await {
         Ok(pay_by_phone) => {
             log::info!("Getting vehicles...");
@@ -192,10 +1075,26 @@ async fn get_vehicles(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/quote",
+    tag = "parking",
+    params(Parking),
+    responses(
+        (status = 200, description = "Quote of a single parking", body = types::Quote),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 500, description = "PayByPhone failed", body = String),
+    )
+)]
 async fn get_quote(
     State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
     Query(parking): Query<Parking>,
 ) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Viewer, Some(&parking.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
     match initalize_pay_by_phone(config, parking.name).await {
         Ok(pay_by_phone) => {
             log::info!("Getting quote...");
@@ -215,82 +1114,613 @@ async fn get_quote(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/park",
+    tag = "parking",
+    request_body = Parking,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Key replaying the response of a previous successful request with the same body"),
+    ),
+    responses(
+        (status = 202, description = "Parked, auto-renewal planned", body = types::Quote),
+        (status = 200, description = "Dry run, the quote was not paid", body = types::Quote),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 409, description = "Another operation is in progress for the account", body = String),
+        (status = 422, description = "The Idempotency-Key was used for another request", body = String),
+        (status = 500, description = "PayByPhone failed", body = String),
+        (status = 502, description = "PayByPhone did not confirm the purchase, which may have been charged", body = String),
+    )
+)]
 async fn park(
-    State(config): State<Arc<RwLock<Accounts>>>,
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    headers: HeaderMap,
     Json(parking): Json<Parking>,
 ) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Parker, Some(&parking.name)) {
+        return (status, Json("Forbidden")).into_response();
+    }
+    let key = headers
+        .get("Idempotency-Key")
+        .and_then(|k| k.to_str().ok())
+        .map(|k| format!("{}:{}", user.name, k));
+    let request = format!("{}:{}:{}", parking.name, parking.duration, parking.dry_run);
+    let mut pending = None;
+    if let Some(key) = &key {
+        match state.idempotency.begin(key, &request) {
+            Begin::Started(started) => pending = Some(started),
+            Begin::Mismatch => {
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(serde_json::Value::from(
+                        "The Idempotency-Key was already used for another request",
+                    )),
+                )
+                    .into_response()
+            }
+            Begin::InFlight => {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(serde_json::Value::from(
+                        "A request with this Idempotency-Key is already in progress",
+                    )),
+                )
+                    .into_response()
+            }
+            Begin::Replay(status, body) => {
+                log::info!("Replaying response of Idempotency-Key {}", key);
+                return (status, [("Idempotent-Replayed", "true")], Json(body)).into_response();
+            }
+        }
+    }
+
+    let event = Event::new(
+        &user.name,
+        "park",
+        Some(&parking.name),
+        json!({ "duration": parking.duration, "dry_run": parking.dry_run }),
+    );
+    let name = parking.name.clone();
+    // The purchase runs to completion even if the client disconnects, so that what was paid is
+    // always recorded.
+    let (status, body) = complete(pending, async move {
+        let (status, body) = match state.locks.try_lock(&parking.name) {
+            Some(_guard) => park_account(state.config, &state.store, parking).await,
+            None => busy(&parking.name),
+        };
+        match status {
+            StatusCode::ACCEPTED => {
+                METRICS.park(&name, "success");
+                METRICS.spend(
+                    &name,
+                    body["totalCost"]["amount"].as_f64().unwrap_or_default(),
+                    body["totalCost"]["currency"].as_str().unwrap_or_default(),
+                );
+            }
+            StatusCode::OK => METRICS.park(&name, "dry_run"),
+            StatusCode::CONFLICT => METRICS.park(&name, "busy"),
+            _ => METRICS.park(&name, "failure"),
+        }
+        state.audit.record(if status.is_success() {
+            event
+                .succeeded(format!("Parked until {}", body["parkingExpiryTime"]))
+                .cost(
+                    body["totalCost"]["amount"].as_f64().unwrap_or_default(),
+                    body["totalCost"]["currency"].as_str().unwrap_or_default(),
+                )
+        } else {
+            event.failed(body.as_str().unwrap_or_default())
+        });
+        (status, body)
+    })
+    .await;
+    (status, Json(body)).into_response()
+}
+
+fn busy(account_name: &str) -> (StatusCode, serde_json::Value) {
+    log::warn!("Account {} is busy", account_name);
+    (
+        StatusCode::CONFLICT,
+        serde_json::Value::from(format!(
+            "Another operation is in progress for account {}",
+            account_name
+        )),
+    )
+}
+
+async fn park_account(
+    config: Arc<RwLock<Accounts>>,
+    store: &Store,
+    parking: Parking,
+) -> (StatusCode, serde_json::Value) {
+    let dry_run = parking.dry_run || config.read().await.settings.dry_run;
     match initalize_pay_by_phone(config.clone(), parking.name.clone()).await {
         Ok(pay_by_phone) => {
             log::info!("Getting vehicles...");
-            match pay_by_phone.park().await {
+            match pay_by_phone.park(dry_run).await {
                 Ok(quote) => {
-                    if let Some(conf) = config
-                        .write()
-                        .await
+                    let mut accounts = config.write().await;
+                    if let Some(conf) = accounts
                         .accounts
                         .iter_mut()
                         .find(|a| a.name == parking.name)
                     {
-                        let duration = (quote.parking_start_time
-                            + chrono::Duration::minutes(parking.duration as i64)
-                            - chrono::Duration::minutes(1)
-                            - quote.parking_expiry_time)
-                            .num_minutes() as i16;
-                        let next_check = quote.parking_expiry_time + chrono::Duration::minutes(1);
-
-                        conf.session = Some(Session {
-                            next_check,
-                            duration,
+                        // A dry run must never turn the paid renewals of a car into simulated ones.
+                        if dry_run && conf.session.as_ref().is_some_and(|s| !s.dry_run) {
+                            log::info!(
+                                "Account {} has a renewal plan, dry run not stored",
+                                conf.name
+                            );
+                            return (
+                                StatusCode::OK,
+                                serde_json::to_value(quote).unwrap_or_default(),
+                            );
+                        }
+                        conf.session = Some(Session::new(
+                            quote.parking_expiry_time,
+                            quote.parking_start_time
+                                + chrono::Duration::minutes(parking.duration as i64),
+                            dry_run,
+                        ));
+                        if !dry_run {
+                            conf.unmanaged = None;
+                        }
+                        EVENTS.publish(Update::SessionStarted {
+                            account: conf.name.clone(),
+                            expiry: quote.parking_expiry_time,
+                            plan: conf.session.clone(),
                         });
+                        persist(store, &accounts);
+                    }
+                    let body = serde_json::to_value(quote).unwrap_or_default();
+                    if dry_run {
+                        (StatusCode::OK, body)
+                    } else {
+                        (StatusCode::ACCEPTED, body)
                     }
-                    (StatusCode::ACCEPTED, Json(quote)).into_response()
                 }
                 Err(e) => {
                     log::error!("{:?}", e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response()
+                    // Replayed instead of retried, as the purchase may have been charged.
+                    let status = if e.is::<Unconfirmed>() {
+                        StatusCode::BAD_GATEWAY
+                    } else {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    };
+                    (status, serde_json::Value::from(e.to_string()))
                 }
             }
         }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(format!("Failed to initialize PayByPhone: {}", e)),
+            serde_json::Value::from(format!("Failed to initialize PayByPhone: {}", e)),
+        ),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body = Credentials,
+    security(()),
+    responses(
+        (status = 200, description = "Logged in, the session and refresh cookies are set", body = User),
+        (status = 401, description = "Invalid username or password", body = String),
+    )
+)]
+async fn login(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(sessions): State<Arc<Sessions>>,
+    State(audit): State<Arc<AuditLog>>,
+    Json(credentials): Json<Credentials>,
+) -> impl IntoResponse {
+    let event = Event::new(&credentials.username, "login", None, json!({}));
+    let user = config
+        .read()
+        .await
+        .users
+        .iter()
+        .find(|u| u.name == credentials.username)
+        .cloned();
+    match user {
+        Some(user)
+            if user
+                .password_hash
+                .as_ref()
+                .is_some_and(|hash| verify_password(&credentials.password, hash)) =>
+        {
+            let tokens = sessions.create(&user.name);
+            log::info!("User {} logged in", user.name);
+            audit.record(event.succeeded(format!("Session {} opened", tokens.session.id)));
+            (StatusCode::OK, sessions.cookies(&tokens), Json(user)).into_response()
+        }
+        _ => {
+            log::warn!("Failed login attempt for user {}", credentials.username);
+            audit.record(event.failed("Invalid username or password"));
+            METRICS.login_failure();
+            (
+                StatusCode::UNAUTHORIZED,
+                Json("Invalid username or password"),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    tag = "auth",
+    security(("refresh" = [])),
+    responses(
+        (status = 200, description = "New session and refresh cookies are set", body = sessions::LoginSession),
+        (status = 401, description = "The refresh token is invalid or expired", body = String),
+    )
+)]
+async fn refresh(State(sessions): State<Arc<Sessions>>, headers: HeaderMap) -> impl IntoResponse {
+    match cookie(&headers, REFRESH_COOKIE).and_then(|token| sessions.refresh(&token)) {
+        Some(tokens) => (
+            StatusCode::OK,
+            sessions.cookies(&tokens),
+            Json(tokens.session.clone()),
+        )
+            .into_response(),
+        None => (
+            StatusCode::UNAUTHORIZED,
+            sessions.clear_cookies(),
+            Json("Session expired"),
         )
             .into_response(),
     }
 }
 
-async fn get_accounts(State(config): State<Arc<RwLock<Accounts>>>) -> impl IntoResponse {
-    (StatusCode::OK, Json(config.read().await.clone())).into_response()
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "auth",
+    security(()),
+    responses(
+        (status = 204, description = "The session is revoked and its cookies cleared"),
+    )
+)]
+async fn logout(
+    State(sessions): State<Arc<Sessions>>,
+    State(audit): State<Arc<AuditLog>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(session) = [SESSION_COOKIE, REFRESH_COOKIE]
+        .iter()
+        .filter_map(|name| cookie(&headers, name))
+        .find_map(|token| sessions.revoke_token(&token))
+    {
+        log::info!("User {} logged out", session.user);
+        audit.record(
+            Event::new(&session.user, "logout", None, json!({}))
+                .succeeded(format!("Session {} closed", session.id)),
+        );
+    }
+    (StatusCode::NO_CONTENT, sessions.clear_cookies()).into_response()
 }
 
-async fn check_renewal(config: Arc<RwLock<Accounts>>) {
-    for account in config.write().await.accounts.iter_mut() {
-        if let Some(session) = &mut account.session {
-            if session.next_check <= chrono::Utc::now() && session.duration > 0 {
-                log::info!("Renewing account {}", account.name);
-                match initalize_pay_by_phone(config.clone(), account.name.clone()).await {
-                    Ok(pay_by_phone) => match pay_by_phone.park().await {
-                        Ok(quote) => {
-                            let duration = (quote.parking_start_time
-                                + chrono::Duration::minutes(session.duration as i64)
-                                - chrono::Duration::minutes(1)
-                                - quote.parking_expiry_time)
-                                .num_minutes() as i16;
-                            let next_check =
-                                quote.parking_expiry_time + chrono::Duration::minutes(1);
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Open sessions of the user, or of everyone for admins", body = Vec<sessions::LoginSession>),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn get_login_sessions(
+    State(sessions): State<Arc<Sessions>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    let mut list = sessions.list();
+    if user.role < Role::Admin {
+        list.retain(|s| s.user == user.name);
+    }
+    (StatusCode::OK, Json(list)).into_response()
+}
 
-                            session.next_check = next_check;
-                            session.duration = duration;
-                            log::info!("Vehicle parked");
-                        }
-                        Err(e) => {
-                            log::error!("{:?}", e);
-                        }
-                    },
-                    Err(e) => {
-                        log::error!("{:?}", e);
-                    }
+#[utoipa::path(
+    post,
+    path = "/sessions/revoke",
+    tag = "auth",
+    request_body = SessionId,
+    responses(
+        (status = 200, description = "The revoked session", body = sessions::LoginSession),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 404, description = "No such session of the user", body = String),
+    )
+)]
+async fn revoke_login_session(
+    State(sessions): State<Arc<Sessions>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Json(session_id): Json<SessionId>,
+) -> impl IntoResponse {
+    let event = Event::new(&user.name, "revoke", None, json!({ "id": session_id.id }));
+    let owned = sessions
+        .list()
+        .iter()
+        .any(|s| s.id == session_id.id && (s.user == user.name || user.role == Role::Admin));
+    match owned.then(|| sessions.revoke(&session_id.id)).flatten() {
+        Some(session) => {
+            log::info!(
+                "Session {} of user {} revoked by {}",
+                session.id,
+                session.user,
+                user.name
+            );
+            audit.record(event.succeeded(format!("Session of user {} revoked", session.user)));
+            (StatusCode::OK, Json(session)).into_response()
+        }
+        None => {
+            audit.record(event.failed("Session not found"));
+            (StatusCode::NOT_FOUND, Json("Session not found")).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit",
+    tag = "admin",
+    params(Filter),
+    responses(
+        (status = 200, description = "The most recent matching events, oldest first", body = Vec<audit::Event>),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 500, description = "The audit log could not be read", body = String),
+    )
+)]
+async fn get_audit(
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Query(filter): Query<Filter>,
+) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Admin, None) {
+        return (status, Json("Forbidden")).into_response();
+    }
+    match audit.query(&filter) {
+        Ok(events) => (StatusCode::OK, Json(events)).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The API is up and the bearer is valid"),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+#[utoipa::path(
+    get,
+    path = "/whoami",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The authenticated user", body = User),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn whoami(Extension(user): Extension<User>) -> impl IntoResponse {
+    (StatusCode::OK, Json(user)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/livez",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "The backend is running"),
+    )
+)]
+async fn livez() -> StatusCode {
+    StatusCode::OK
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "Ready to serve", body = health::Readiness),
+        (status = 503, description = "Not ready", body = health::Readiness),
+    )
+)]
+async fn get_readiness(config: Arc<RwLock<Accounts>>) -> impl IntoResponse {
+    let readiness = HEALTH.readiness(&*config.read().await);
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid METRICS_BEARER"),
+    )
+)]
+async fn get_metrics(
+    config: Arc<RwLock<Accounts>>,
+    bearer: Option<String>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if let Some(bearer) = bearer {
+        let authorized = headers
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|token| token == bearer);
+        if !authorized {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    match METRICS.render(&*config.read().await) {
+        Ok(metrics) => (
+            StatusCode::OK,
+            [("Content-Type", prometheus::TEXT_FORMAT)],
+            metrics,
+        )
+            .into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "The accounts the user has access to", body = Accounts),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn get_accounts(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    let mut accounts = config.read().await.clone();
+    accounts.accounts.retain(|a| user.can_access(&a.name));
+    (StatusCode::OK, Json(accounts)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "Live session and renewal plan of the accounts the user has access to", body = status::Status),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn get_status(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(status_cache): State<Arc<StatusCache>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    let mut accounts = config.read().await.accounts.clone();
+    accounts.retain(|a| user.can_access(&a.name));
+    (StatusCode::OK, Json(status_cache.status(accounts).await)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "Stream of server-sent events, one JSON update each", body = Update, content_type = "text/event-stream"),
+        (status = 401, description = "Not authenticated", body = String),
+    )
+)]
+async fn get_events(Extension(user): Extension<User>) -> impl IntoResponse {
+    EVENTS.stream(user)
+}
+
+#[utoipa::path(
+    post,
+    path = "/reload",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The config was reloaded", body = String),
+        (status = 401, description = "Not authenticated", body = String),
+        (status = 403, description = "Not allowed on this account", body = String),
+        (status = 500, description = "The config could not be read", body = String),
+    )
+)]
+async fn reload_config(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    if let Err(status) = authorize(&user, Role::Admin, None) {
+        return (status, Json("Forbidden")).into_response();
+    }
+    let event = Event::new(&user.name, "reload", None, json!({}));
+    let fresh = match config::read("config.yaml") {
+        Ok(fresh) => fresh,
+        Err(e) => {
+            log::error!("{:?}", e);
+            audit.record(event.failed(e.to_string()));
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response();
+        }
+    };
+    let mut accounts = config.write().await;
+    accounts.reload(fresh);
+    log::info!("Config reloaded, {} accounts", accounts.accounts.len());
+    audit.record(event.succeeded("Config reloaded"));
+    EVENTS.publish(Update::ConfigReloaded);
+    (StatusCode::OK, Json("Config reloaded")).into_response()
+}
+
+async fn discover_sessions(config: Arc<RwLock<Accounts>>) {
+    let names: Vec<String> = config
+        .read()
+        .await
+        .accounts
+        .iter()
+        .map(|a| a.name.clone())
+        .collect();
+
+    for name in names {
+        log::debug!("Discovering sessions of account {}", name);
+        let live = match initalize_pay_by_phone(config.clone(), name.clone()).await {
+            Ok(pay_by_phone) => match pay_by_phone.current_session().await {
+                Ok(live) => live,
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    continue;
+                }
+            },
+            Err(e) => {
+                log::error!("{:?}", e);
+                continue;
+            }
+        };
+
+        if let Some(conf) = config
+            .write()
+            .await
+            .accounts
+            .iter_mut()
+            .find(|a| a.name == name)
+        {
+            conf.unmanaged = live.filter(|live| {
+                match chrono::DateTime::parse_from_rfc3339(live.expire_time.as_str()) {
+                    Ok(expiry) => !conf
+                        .session
+                        .as_ref()
+                        .is_some_and(|s| s.owns(expiry.with_timezone(&chrono::Utc))),
+                    Err(_) => true,
                 }
+            });
+            if conf.unmanaged.is_some() {
+                log::info!("Found unmanaged session for account {}", conf.name);
             }
         }
     }
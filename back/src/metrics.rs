@@ -0,0 +1,150 @@
+use crate::config::Accounts;
+use chrono::Utc;
+use prometheus::core::Collector;
+use prometheus::{
+    CounterVec, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+pub(crate) struct Metrics {
+    registry: Registry,
+    parks: IntCounterVec,
+    renewals: IntCounterVec,
+    upstream_duration: HistogramVec,
+    upstream_errors: IntCounterVec,
+    upstream_auth_failures: IntCounterVec,
+    login_failures: IntCounter,
+    spend: CounterVec,
+    managed_sessions: IntGauge,
+    session_expiry: GaugeVec,
+}
+
+pub(crate) static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("autopbf".to_string()), None).unwrap();
+        let metrics = Self {
+            parks: IntCounterVec::new(
+                Opts::new("parks_total", "Parkings requested through the API"),
+                &["account", "outcome"],
+            )
+            .unwrap(),
+            renewals: IntCounterVec::new(
+                Opts::new("renewals_total", "Renewals handled by the scheduler"),
+                &["account", "outcome"],
+            )
+            .unwrap(),
+            upstream_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "upstream_request_duration_seconds",
+                    "Latency of the requests to PayByPhone",
+                ),
+                &["endpoint"],
+            )
+            .unwrap(),
+            upstream_errors: IntCounterVec::new(
+                Opts::new(
+                    "upstream_errors_total",
+                    "Failed or unsuccessful requests to PayByPhone",
+                ),
+                &["endpoint"],
+            )
+            .unwrap(),
+            upstream_auth_failures: IntCounterVec::new(
+                Opts::new(
+                    "upstream_auth_failures_total",
+                    "Failures to get the PayByPhone API key or to log in to PayByPhone",
+                ),
+                &["stage"],
+            )
+            .unwrap(),
+            login_failures: IntCounter::new("login_failures_total", "Failed logins to the API")
+                .unwrap(),
+            spend: CounterVec::new(
+                Opts::new("spend_total", "Amount paid for parkings and renewals"),
+                &["account", "currency"],
+            )
+            .unwrap(),
+            managed_sessions: IntGauge::new("managed_sessions", "Sessions with a renewal plan")
+                .unwrap(),
+            session_expiry: GaugeVec::new(
+                Opts::new(
+                    "session_expiry_seconds",
+                    "Seconds until the current ticket of a managed session expires",
+                ),
+                &["account"],
+            )
+            .unwrap(),
+            registry,
+        };
+        let collectors: [Box<dyn Collector>; 9] = [
+            Box::new(metrics.parks.clone()),
+            Box::new(metrics.renewals.clone()),
+            Box::new(metrics.upstream_duration.clone()),
+            Box::new(metrics.upstream_errors.clone()),
+            Box::new(metrics.upstream_auth_failures.clone()),
+            Box::new(metrics.login_failures.clone()),
+            Box::new(metrics.spend.clone()),
+            Box::new(metrics.managed_sessions.clone()),
+            Box::new(metrics.session_expiry.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    pub(crate) fn park(&self, account: &str, outcome: &str) {
+        self.parks.with_label_values(&[account, outcome]).inc();
+    }
+
+    pub(crate) fn renewal(&self, account: &str, outcome: &str) {
+        self.renewals.with_label_values(&[account, outcome]).inc();
+    }
+
+    pub(crate) fn spend(&self, account: &str, amount: f64, currency: &str) {
+        self.spend
+            .with_label_values(&[account, currency])
+            .inc_by(amount.max(0.0));
+    }
+
+    pub(crate) fn upstream(&self, endpoint: &str, start: Instant, success: bool) {
+        self.upstream_duration
+            .with_label_values(&[endpoint])
+            .observe(start.elapsed().as_secs_f64());
+        if !success {
+            self.upstream_errors.with_label_values(&[endpoint]).inc();
+        }
+    }
+
+    pub(crate) fn upstream_auth_failure(&self, stage: &str) {
+        self.upstream_auth_failures
+            .with_label_values(&[stage])
+            .inc();
+    }
+
+    pub(crate) fn login_failure(&self) {
+        self.login_failures.inc();
+    }
+
+    /// Renders every metric in the Prometheus text format, with the session gauges taken from the config.
+    pub(crate) fn render(&self, accounts: &Accounts) -> Result<String, prometheus::Error> {
+        let now = Utc::now();
+        self.session_expiry.reset();
+        let mut managed = 0;
+        for account in accounts.accounts.iter() {
+            if let Some(session) = account.session.as_ref().filter(|s| s.is_active()) {
+                managed += 1;
+                self.session_expiry
+                    .with_label_values(&[account.name.as_str()])
+                    .set((session.expected_expiry() - now).num_seconds() as f64);
+            }
+        }
+        self.managed_sessions.set(managed);
+
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
@@ -1,17 +1,206 @@
-use axum::{extract::Request, http::StatusCode, middleware::Next, response::Response};
+use crate::config::{Accounts, Cors, Role, User};
+use crate::jwt::JwtValidator;
+use crate::leader::Lease;
+use crate::ratelimit::{client, too_many_requests, RateLimits};
+use crate::sessions::{cookie, Sessions, SESSION_COOKIE};
+use axum::{
+    extract::Request,
+    http::{HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::Span;
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
 
 pub async fn auth_middleware(
-    req: Request,
+    mut req: Request,
     next: Next,
-    bearer_token: Arc<String>,
+    bearer_token: Arc<Option<String>>,
+    config: Arc<RwLock<Accounts>>,
+    sessions: Arc<Sessions>,
+    jwt: Arc<Option<JwtValidator>>,
+    limits: Arc<RateLimits>,
 ) -> Result<Response, StatusCode> {
     if let Some(auth_header) = req.headers().get("Authorization") {
         if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str == format!("Bearer {}", bearer_token.as_str()) {
-                return Ok(next.run(req).await);
+            if let Some(token) = auth_str.strip_prefix("Bearer ") {
+                // Session cookies are too long to be guessed, only bearer tokens are counted.
+                let client = client(&req);
+                if let Some(wait) = limits.locked(&client) {
+                    return Ok(too_many_requests(wait));
+                }
+                if let Some(user) = authenticate(token, &bearer_token, &config, &jwt).await {
+                    limits.succeed(&client);
+                    req.extensions_mut().insert(user);
+                    return Ok(next.run(req).await);
+                }
+                limits.fail(&client);
             }
         }
     }
+    if let Some(name) =
+        cookie(req.headers(), SESSION_COOKIE).and_then(|token| sessions.authenticate(&token))
+    {
+        let user = config
+            .read()
+            .await
+            .users
+            .iter()
+            .find(|u| u.name == name)
+            .cloned();
+        if let Some(user) = user {
+            req.extensions_mut().insert(user);
+            return Ok(next.run(req).await);
+        }
+    }
     Err(StatusCode::UNAUTHORIZED)
 }
+
+async fn authenticate(
+    token: &str,
+    bearer_token: &Option<String>,
+    config: &RwLock<Accounts>,
+    jwt: &Option<JwtValidator>,
+) -> Option<User> {
+    if bearer_token.as_deref() == Some(token) {
+        return Some(User {
+            name: "bearer".to_string(),
+            token_hash: String::new(),
+            password_hash: None,
+            role: Role::Admin,
+            accounts: vec!["*".to_string()],
+        });
+    }
+    if let Some(jwt) = jwt.as_ref().filter(|_| token.split('.').count() == 3) {
+        let users = config.read().await.users.clone();
+        return match jwt.validate(token, &users).await {
+            Ok(user) => Some(user),
+            Err(e) => {
+                log::warn!("Rejected JWT: {}", e);
+                None
+            }
+        };
+    }
+    let hash = hash_token(token);
+    config
+        .read()
+        .await
+        .users
+        .iter()
+        .find(|u| u.token_hash.eq_ignore_ascii_case(&hash))
+        .cloned()
+}
+
+pub fn authorize(user: &User, role: Role, account_name: Option<&str>) -> Result<(), StatusCode> {
+    if let Some(account_name) = account_name {
+        Span::current().record("account", account_name);
+    }
+    if user.role < role || account_name.is_some_and(|a| !user.can_access(a)) {
+        log::warn!(
+            "User {} is not allowed to act as {:?} on account {:?}",
+            user.name,
+            role,
+            account_name
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+pub async fn leader_middleware(
+    req: Request,
+    next: Next,
+    lease: Arc<Lease>,
+) -> Result<Response, StatusCode> {
+    if req.method() == Method::GET || lease.is_leader() {
+        return Ok(next.run(req).await);
+    }
+    log::warn!(
+        "Rejecting {} {}: {} is not the leader",
+        req.method(),
+        req.uri(),
+        lease.holder()
+    );
+    Err(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+pub(crate) fn cors_layer(cors: &Cors) -> Result<CorsLayer, Box<dyn Error + Send + Sync>> {
+    let wildcard = cors.allowed_origins.iter().any(|o| o == "*")
+        || cors.allowed_methods.iter().any(|m| m == "*")
+        || cors.allowed_headers.iter().any(|h| h == "*");
+    if cors.allow_credentials && wildcard {
+        return Err(Box::from(
+            "CORS credentials cannot be allowed together with a \"*\" wildcard",
+        ));
+    }
+
+    let origins = if cors.allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            cors.allowed_origins
+                .iter()
+                .map(|o| o.parse())
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_credentials(cors.allow_credentials)
+        .max_age(Duration::from_secs(cors.max_age));
+    layer = if cors.allowed_methods.iter().any(|m| m == "*") {
+        layer.allow_methods(tower_http::cors::Any)
+    } else {
+        layer.allow_methods(
+            cors.allowed_methods
+                .iter()
+                .map(|m| m.parse())
+                .collect::<Result<Vec<Method>, _>>()?,
+        )
+    };
+    layer = if cors.allowed_headers.iter().any(|h| h == "*") {
+        layer.allow_headers(tower_http::cors::Any)
+    } else {
+        layer.allow_headers(
+            cors.allowed_headers
+                .iter()
+                .map(|h| h.parse())
+                .collect::<Result<Vec<axum::http::HeaderName>, _>>()?,
+        )
+    };
+    Ok(layer)
+}
+
+/// Span wrapping the handling of a request, tagged with the ID set by `SetRequestIdLayer` and
+/// with the account the request acts on once it is authorized.
+pub fn request_span(req: &Request) -> Span {
+    let id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        id,
+        method = %req.method(),
+        path = req.uri().path(),
+        account = tracing::field::Empty,
+    )
+}
+
+/// Flags the responses of the routes superseded by `/api/v1`.
+pub async fn deprecation(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert("Deprecation", HeaderValue::from_static("true"));
+    response
+}
@@ -0,0 +1,62 @@
+use crate::sessions::{REFRESH_COOKIE, SESSION_COOKIE};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Deprecated;
+use utoipa::{Modify, OpenApi};
+use utoipa_axum::router::OpenApiRouter;
+
+/// OpenAPI document of the backend. The routes under `/api` are added to it as they are mounted, so it
+/// only lists the ones served outside of it.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "AutoRenewPayByPhone"),
+    paths(crate::livez, crate::get_readiness, crate::get_metrics),
+    modifiers(&Security),
+    security(("bearer" = []), ("session" = [])),
+    tags(
+        (name = "accounts", description = "Car accounts and their live state"),
+        (name = "parking", description = "Quotes, parkings and live PayByPhone sessions"),
+        (name = "renewal", description = "Auto-renewal plans"),
+        (name = "auth", description = "Users, logins and sessions"),
+        (name = "admin", description = "Administration, restricted to admins"),
+        (name = "health", description = "Liveness, readiness and metrics"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("User token, legacy admin bearer or OIDC JWT"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(SESSION_COOKIE))),
+        );
+        components.add_security_scheme(
+            "refresh",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(REFRESH_COOKIE))),
+        );
+    }
+}
+
+/// Marks every operation of the router as deprecated.
+pub(crate) fn deprecate<S>(mut router: OpenApiRouter<S>) -> OpenApiRouter<S>
+where
+    S: Send + Sync + Clone + 'static,
+{
+    for item in router.get_openapi_mut().paths.paths.values_mut() {
+        for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+            operation.deprecated = Some(Deprecated::True);
+        }
+    }
+    router
+}
@@ -1,3 +1,7 @@
+use crate::config::Config;
+use crate::health::HEALTH;
+use crate::logging::{redact, REDACTED};
+use crate::metrics::METRICS;
 use crate::types::{
     Account, Auth, Duration, GetParkingSession, GetQuote, GetRateOptions, ParkingOption,
     ParkingSession, PaymentMethod, PaymentPayload, PostQuote, Quote, Vehicle,
@@ -7,8 +11,16 @@ use reqwest::{Client, Method, Response};
 use serde::Serialize;
 use serde_json::json;
 use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+use tracing::Instrument;
 
-#[derive(Debug, Clone)]
+/// Minutes bought by every parking and renewal.
+pub(crate) const PURCHASE_MINUTES: i16 = 15;
+/// Time after which a call to PayByPhone is given up, so that a renewal never outlasts its lease.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Clone)]
 pub struct PayByPhone {
     plate: String,
     lot: i32,
@@ -41,6 +53,37 @@ const BASE_HEADERS: Header = Header {
     connection: "keep-alive",
 };
 
+/// Failure of a purchase sent to PayByPhone without its confirmation, that may have been charged.
+#[derive(Debug)]
+pub(crate) struct Unconfirmed(Box<dyn Error + Send + Sync>);
+
+impl fmt::Display for Unconfirmed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Purchase not confirmed, it may have been charged: {}",
+            self.0
+        )
+    }
+}
+
+impl Error for Unconfirmed {}
+
+impl fmt::Debug for PayByPhone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PayByPhone")
+            .field("plate", &self.plate)
+            .field("lot", &self.lot)
+            .field("login", &self.login)
+            .field("password", &REDACTED)
+            .field("payment_account_id", &REDACTED)
+            .field("api_key", &self.api_key.as_ref().map(|_| REDACTED))
+            .field("auth", &self.auth.as_ref().map(|_| REDACTED))
+            .field("account_id", &self.account_id)
+            .finish()
+    }
+}
+
 impl PayByPhone {
     pub fn new(
         plate: String,
@@ -58,61 +101,110 @@ impl PayByPhone {
             api_key: None,
             auth: None,
             account_id: None,
-            client: Client::new(),
+            client: Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub(crate) async fn connect(account: &Config) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut pay_by_phone = Self::new(
+            account.plate.clone(),
+            account.lot,
+            account.pay_by_phone.login.clone(),
+            account.pay_by_phone.password.clone(),
+            account.pay_by_phone.payment_account_id.clone(),
+        );
+        match pay_by_phone.init().await {
+            Ok(_) => {
+                log::info!("PayByPhone initialized");
+                Ok(pay_by_phone)
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+                Err(Box::from(format!("Failed to initialize PayByPhone: {}", e)))
+            }
         }
     }
 
     pub async fn init(&mut self) -> Result<(), Box<dyn Error>> {
         log::info!("Getting API key...");
-        match self
+        let start = Instant::now();
+        let response = self
             .client
             .get("https://m2.paybyphone.fr/static/js/main.0aec44c0.chunk.js")
             .send()
-            .await
-        {
+            .instrument(tracing::info_span!("upstream", endpoint = "api_key"))
+            .await;
+        METRICS.upstream(
+            "api_key",
+            start,
+            response.as_ref().is_ok_and(|r| r.status().is_success()),
+        );
+        HEALTH.upstream(
+            response
+                .as_ref()
+                .is_ok_and(|r| !r.status().is_server_error()),
+        );
+        match response {
             Ok(resp) => match resp.text().await {
                 Ok(text) => {
                     let pattern = Regex::new(r#"paymentService:\{[^}]*apiKey:"(.*?)""#).unwrap();
-                    self.api_key = Some(
-                        pattern
-                            .captures(&text)
-                            .unwrap()
-                            .get(1)
-                            .unwrap()
-                            .as_str()
-                            .to_string(),
-                    );
+                    match pattern.captures(&text).and_then(|c| c.get(1)) {
+                        Some(api_key) => {
+                            self.api_key = Some(api_key.as_str().to_string());
+                            HEALTH.api_key(true);
+                        }
+                        None => {
+                            METRICS.upstream_auth_failure("api_key");
+                            HEALTH.api_key(false);
+                            return Err(Box::from("API key not found"));
+                        }
+                    }
 
                     match self.get_user_access_token().await {
                         Ok(_) => Ok(()),
                         Err(e) => Err(e),
                     }
                 }
-                Err(e) => Err(Box::new(e)),
+                Err(e) => {
+                    METRICS.upstream_auth_failure("api_key");
+                    HEALTH.api_key(false);
+                    Err(Box::new(e))
+                }
             },
-            Err(e) => Err(Box::new(e)),
+            Err(e) => {
+                METRICS.upstream_auth_failure("api_key");
+                HEALTH.api_key(false);
+                Err(Box::new(e))
+            }
         }
     }
 
     async fn get<T: Serialize + ?Sized>(
         &self,
+        endpoint: &str,
         url: &str,
         params: Option<&T>,
     ) -> Result<Response, Box<dyn Error + Send + Sync>> {
-        self.request(Method::GET, url, params).await
+        self.request(Method::GET, endpoint, url, params).await
     }
 
     async fn post<T: Serialize + ?Sized>(
         &self,
+        endpoint: &str,
         url: &str,
         params: Option<&T>,
     ) -> Result<Response, Box<dyn Error + Send + Sync>> {
-        self.request(Method::POST, url, params).await
+        self.request(Method::POST, endpoint, url, params).await
     }
 
+    /// Sends a request to the PayByPhone API, timed under the given endpoint name.
     pub async fn request<T: Serialize + ?Sized>(
         &self,
         method: Method,
+        endpoint: &str,
         url: &str,
         params: Option<&T>,
     ) -> Result<Response, Box<dyn Error + Send + Sync>> {
@@ -143,18 +235,32 @@ impl PayByPhone {
             match method {
                 Method::GET => {
                     request = request.query(params);
-                    log::debug!("Request: {:?}", json!(params));
+                    log::debug!("Request: {}", redact(&json!(params)));
                 }
                 Method::POST => {
-                    log::debug!("Request: {:?}", json!(params));
+                    log::debug!("Request: {}", redact(&json!(params)));
                     request = request.json(&json!(params));
                 }
                 _ => {}
             }
         }
-        log::debug!("Request: {:?}", request);
 
-        match request.send().await {
+        let start = Instant::now();
+        let response = request
+            .send()
+            .instrument(tracing::info_span!("upstream", endpoint, %method))
+            .await;
+        METRICS.upstream(
+            endpoint,
+            start,
+            response.as_ref().is_ok_and(|r| r.status().is_success()),
+        );
+        HEALTH.upstream(
+            response
+                .as_ref()
+                .is_ok_and(|r| !r.status().is_server_error()),
+        );
+        match response {
             Ok(resp) => Ok(resp),
             Err(e) => Err(Box::new(e)),
         }
@@ -162,7 +268,7 @@ impl PayByPhone {
 
     pub async fn get_vehicles(&self) -> Result<Vec<Vehicle>, Box<dyn Error + Send + Sync>> {
         log::info!("Getting user vehicles...");
-        match self.get::<String>("https://consumer.paybyphoneapis.com/identity/profileservice/v1/members/vehicles/paybyphone", None).await {
+        match self.get::<String>("vehicles", "https://consumer.paybyphoneapis.com/identity/profileservice/v1/members/vehicles/paybyphone", None).await {
             Ok(resp) => {
                 match resp.text().await {
                     Ok(json) => {
@@ -182,16 +288,31 @@ impl PayByPhone {
         }
     }
 
-    pub(crate) async fn park(&self) -> Result<Quote, Box<dyn Error + Send + Sync>> {
+    pub(crate) async fn park(&self, dry_run: bool) -> Result<Quote, Box<dyn Error + Send + Sync>> {
         log::info!("Parking user...");
         match self.get_rate_option().await {
             Ok(parking_options) => {
                 log::info!("Got rate options");
                 let rate = parking_options[0].clone().rate_option_id;
-                match self.get_quote(15, rate.as_str()).await {
-                    Ok(quote) => match self.post_quote(quote.clone(), 15, rate.as_str()).await {
+                match self.get_quote(PURCHASE_MINUTES, rate.as_str()).await {
+                    Ok(quote) if dry_run => {
+                        log::info!(
+                            "Dry run: would buy {} minutes for {} at lot {} until {}, costing {} {}",
+                            PURCHASE_MINUTES,
+                            self.plate,
+                            self.lot,
+                            quote.parking_expiry_time,
+                            quote.total_cost.amount,
+                            quote.total_cost.currency
+                        );
+                        Ok(quote)
+                    }
+                    Ok(quote) => match self
+                        .post_quote(quote.clone(), PURCHASE_MINUTES, rate.as_str())
+                        .await
+                    {
                         Ok(_) => Ok(quote),
-                        Err(e) => Err(e),
+                        Err(e) => Err(Box::new(Unconfirmed(e))),
                     },
                     Err(e) => Err(e),
                 }
@@ -208,7 +329,8 @@ impl PayByPhone {
             ("password", &self.password),
             ("client_id", "paybyphone_webapp"),
         ];
-        match self
+        let start = Instant::now();
+        let response = self
             .client
             .post("https://auth.paybyphoneapis.com/token")
             .header("User-Agent", BASE_HEADERS.user_agent)
@@ -222,14 +344,35 @@ impl PayByPhone {
             .header("X-Pbp-ClientType", "WebApp")
             .form(&params)
             .send()
-            .await
-        {
+            .instrument(tracing::info_span!("upstream", endpoint = "token"))
+            .await;
+        METRICS.upstream(
+            "token",
+            start,
+            response.as_ref().is_ok_and(|r| r.status().is_success()),
+        );
+        HEALTH.upstream(
+            response
+                .as_ref()
+                .is_ok_and(|r| !r.status().is_server_error()),
+        );
+        match response {
             Ok(resp) => match resp.text().await {
                 Ok(json) => {
-                    self.auth = serde_json::from_str(&json).unwrap();
+                    match serde_json::from_str(&json) {
+                        Ok(auth) => self.auth = auth,
+                        Err(e) => {
+                            METRICS.upstream_auth_failure("login");
+                            return Err(Box::new(e));
+                        }
+                    }
                     log::info!("Getting user account ID...");
                     match self
-                        .get::<String>("https://consumer.paybyphoneapis.com/parking/accounts", None)
+                        .get::<String>(
+                            "accounts",
+                            "https://consumer.paybyphoneapis.com/parking/accounts",
+                            None,
+                        )
                         .await
                     {
                         Ok(resp) => match resp.text().await {
@@ -243,9 +386,15 @@ impl PayByPhone {
                         Err(e) => Err(e),
                     }
                 }
-                Err(e) => Err(Box::new(e)),
+                Err(e) => {
+                    METRICS.upstream_auth_failure("login");
+                    Err(Box::new(e))
+                }
             },
-            Err(e) => Err(Box::new(e)),
+            Err(e) => {
+                METRICS.upstream_auth_failure("login");
+                Err(Box::new(e))
+            }
         }
     }
 
@@ -257,6 +406,7 @@ impl PayByPhone {
         log::info!("Getting quote...");
         match self
             .get(
+                "quote",
                 format!(
                     "https://consumer.paybyphoneapis.com/parking/accounts/{}/quote",
                     self.account_id.clone().unwrap()
@@ -292,6 +442,7 @@ impl PayByPhone {
         log::info!("Post quote...");
         match self
             .post(
+                "purchase",
                 format!(
                     "https://consumer.paybyphoneapis.com/parking/accounts/{}/sessions/",
                     self.account_id.clone().unwrap()
@@ -335,6 +486,7 @@ impl PayByPhone {
     ) -> Result<Vec<ParkingSession>, Box<dyn Error + Send + Sync>> {
         match self
             .get(
+                "sessions",
                 format!(
                     "https://consumer.paybyphoneapis.com/parking/accounts/{}/sessions",
                     self.account_id.clone().unwrap()
@@ -358,17 +510,21 @@ impl PayByPhone {
     }
 
     pub(crate) async fn check(&self) -> Result<ParkingSession, Box<dyn Error + Send + Sync>> {
+        match self.current_session().await {
+            Ok(Some(session)) => Ok(session),
+            Ok(None) => Err(Box::from("No active parking session found".to_string())),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) async fn current_session(
+        &self,
+    ) -> Result<Option<ParkingSession>, Box<dyn Error + Send + Sync>> {
         log::info!("Checking user parking sessions...");
         match self.get_parking_session().await {
-            Ok(session) => {
-                match session
-                    .iter()
-                    .find(|s| s.vehicle.license_plate == self.plate)
-                {
-                    Some(s) => Ok(s.clone()),
-                    None => Err(Box::from("No active parking session found".to_string())),
-                }
-            }
+            Ok(session) => Ok(session
+                .into_iter()
+                .find(|s| s.vehicle.license_plate == self.plate)),
             Err(e) => Err(e),
         }
     }
@@ -386,10 +542,13 @@ impl PayByPhone {
         }
     }
 
-    async fn get_rate_option(&self) -> Result<Vec<ParkingOption>, Box<dyn Error + Send + Sync>> {
+    pub(crate) async fn get_rate_option(
+        &self,
+    ) -> Result<Vec<ParkingOption>, Box<dyn Error + Send + Sync>> {
         log::info!("Getting rate option...");
         match self
             .get(
+                "rate_options",
                 format!(
                     "https://consumer.paybyphoneapis.com/parking/locations/{}/rateOptions",
                     self.lot
@@ -0,0 +1,219 @@
+use crate::config::User;
+use crate::tls::TlsListener;
+use axum::extract::connect_info::Connected;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::serve::IncomingStream;
+use axum::Json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Failed authentications allowed from an address before it gets locked out.
+const FREE_FAILURES: u32 = 5;
+/// Lockout after the first failure past the free ones, doubled by every following one.
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// Time without failure after which an address starts over.
+const FAILURES_TTL: Duration = Duration::from_secs(60 * 60);
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Requests allowed per minute to each user, refilled continuously, with bursts of up to a minute.
+struct Buckets {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Buckets {
+    fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a request from the bucket of the user, or returns how long until one is available.
+    fn take(&self, user: &str) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let capacity = self.per_minute as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(user.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let refill = (now - bucket.updated).as_secs_f64() * capacity / 60.0;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * 60.0 / capacity,
+            ))
+        }
+    }
+}
+
+/// Locks out the addresses guessing bearer tokens or passwords, and limits the rate of the requests
+/// of every user.
+pub(crate) struct RateLimits {
+    failures: Mutex<HashMap<String, Failures>>,
+    requests: Buckets,
+    upstream: Buckets,
+}
+
+impl RateLimits {
+    pub(crate) fn new(per_minute: u32, upstream_per_minute: u32) -> Self {
+        Self {
+            failures: Mutex::new(HashMap::new()),
+            requests: Buckets::new(per_minute),
+            upstream: Buckets::new(upstream_per_minute),
+        }
+    }
+
+    /// Returns how long the address is still locked out for, if it is.
+    pub(crate) fn locked(&self, client: &str) -> Option<Duration> {
+        let now = Instant::now();
+        self.failures
+            .lock()
+            .unwrap()
+            .get(client)
+            .filter(|f| f.locked_until > now)
+            .map(|f| f.locked_until - now)
+    }
+
+    /// Counts a failed authentication, locking the address out once it has failed too often.
+    pub(crate) fn fail(&self, client: &str) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, f| now - f.last < FAILURES_TTL);
+        let entry = failures.entry(client.to_string()).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: now,
+        });
+        entry.count += 1;
+        entry.last = now;
+        if entry.count > FREE_FAILURES {
+            let lockout = BASE_LOCKOUT
+                .saturating_mul(2u32.saturating_pow(entry.count - FREE_FAILURES - 1))
+                .min(MAX_LOCKOUT);
+            entry.locked_until = now + lockout;
+            log::warn!(
+                "Locking out {} for {:?} after {} failed authentications",
+                client,
+                lockout,
+                entry.count
+            );
+        }
+    }
+
+    pub(crate) fn succeed(&self, client: &str) {
+        self.failures.lock().unwrap().remove(client);
+    }
+}
+
+/// Address of the client of a TCP or TLS connection.
+#[derive(Clone, Copy)]
+pub(crate) struct Peer(IpAddr);
+
+impl Connected<IncomingStream<'_, tokio::net::TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, tokio::net::TcpListener>) -> Self {
+        Peer(stream.remote_addr().ip())
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        Peer(stream.remote_addr().ip())
+    }
+}
+
+/// Address of the client, or the one its proxy forwarded for it when listening on a Unix socket.
+pub(crate) fn client(req: &Request) -> String {
+    if let Some(ConnectInfo(Peer(address))) = req.extensions().get::<ConnectInfo<Peer>>() {
+        return address.to_string();
+    }
+    req.headers()
+        .get("X-Forwarded-For")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+pub(crate) fn too_many_requests(wait: Duration) -> Response {
+    // Rounded up, so that a retry after this delay is accepted.
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, seconds.to_string())],
+        Json("Too many requests"),
+    )
+        .into_response()
+}
+
+/// Rejects the logins of locked out addresses, and counts the failed ones.
+pub(crate) async fn lockout(
+    State(limits): State<Arc<RateLimits>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let client = client(&req);
+    if let Some(wait) = limits.locked(&client) {
+        return too_many_requests(wait);
+    }
+    let response = next.run(req).await;
+    match response.status() {
+        StatusCode::UNAUTHORIZED => limits.fail(&client),
+        status if status.is_success() => limits.succeed(&client),
+        _ => {}
+    }
+    response
+}
+
+async fn limit(buckets: &Buckets, req: Request, next: Next) -> Response {
+    if let Some(user) = req.extensions().get::<User>() {
+        if let Err(wait) = buckets.take(&user.name) {
+            log::warn!("Rate limiting {}", user.name);
+            return too_many_requests(wait);
+        }
+    }
+    next.run(req).await
+}
+
+/// Limits the rate of the requests of every authenticated user.
+pub(crate) async fn rate_limit(
+    State(limits): State<Arc<RateLimits>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    limit(&limits.requests, req, next).await
+}
+
+/// Limits further the rate of the requests calling PayByPhone, each of them logging in to it.
+pub(crate) async fn upstream_limit(
+    State(limits): State<Arc<RateLimits>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    limit(&limits.upstream, req, next).await
+}
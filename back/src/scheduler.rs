@@ -0,0 +1,576 @@
+use crate::audit::{AuditLog, Event, SCHEDULER};
+use crate::clock::Clock;
+use crate::config::{Accounts, Config, Session, Settings};
+use crate::events::{Update, EVENTS};
+use crate::leader::Lease;
+use crate::locks::Locks;
+use crate::logging::correlation_id;
+use crate::metrics::METRICS;
+use crate::paybyphone::PayByPhone;
+use crate::types::Quote;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::Instrument;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Purchase {
+    pub(crate) start: DateTime<Utc>,
+    pub(crate) expiry: DateTime<Utc>,
+    pub(crate) cost: f64,
+    pub(crate) currency: String,
+}
+
+impl From<Quote> for Purchase {
+    fn from(quote: Quote) -> Self {
+        Self {
+            start: quote.parking_start_time,
+            expiry: quote.parking_expiry_time,
+            cost: quote.total_cost.amount,
+            currency: quote.total_cost.currency,
+        }
+    }
+}
+
+pub(crate) trait Provider {
+    type Connection: Connection;
+
+    async fn connect(
+        &self,
+        account: &Config,
+    ) -> Result<Self::Connection, Box<dyn Error + Send + Sync>>;
+}
+
+pub(crate) trait Connection {
+    async fn live_expiry(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error + Send + Sync>>;
+
+    async fn buy(&self, dry_run: bool) -> Result<Purchase, Box<dyn Error + Send + Sync>>;
+}
+
+pub(crate) struct PayByPhoneProvider {
+    pub(crate) lease: Arc<Lease>,
+}
+
+impl Provider for PayByPhoneProvider {
+    type Connection = Leased<PayByPhone>;
+
+    async fn connect(
+        &self,
+        account: &Config,
+    ) -> Result<Leased<PayByPhone>, Box<dyn Error + Send + Sync>> {
+        Ok(Leased {
+            connection: PayByPhone::connect(account).await?,
+            lease: self.lease.clone(),
+        })
+    }
+}
+
+/// Connection refreshing the lease before every purchase, and refusing to buy once another
+/// instance took it over, e.g. while a slow call to PayByPhone was in progress.
+pub(crate) struct Leased<C> {
+    connection: C,
+    lease: Arc<Lease>,
+}
+
+impl<C: Connection> Connection for Leased<C> {
+    async fn live_expiry(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error + Send + Sync>> {
+        self.connection.live_expiry().await
+    }
+
+    async fn buy(&self, dry_run: bool) -> Result<Purchase, Box<dyn Error + Send + Sync>> {
+        if !self.lease.heartbeat(Utc::now())? {
+            return Err("Lost the renewal lease to another instance".into());
+        }
+        self.connection.buy(dry_run).await
+    }
+}
+
+impl Connection for PayByPhone {
+    async fn live_expiry(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error + Send + Sync>> {
+        match self.current_session().await {
+            Ok(Some(live)) => match DateTime::parse_from_rfc3339(live.expire_time.as_str()) {
+                Ok(expiry) => Ok(Some(expiry.with_timezone(&Utc))),
+                Err(e) => Err(Box::new(e)),
+            },
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn buy(&self, dry_run: bool) -> Result<Purchase, Box<dyn Error + Send + Sync>> {
+        match self.park(dry_run).await {
+            Ok(quote) => Ok(Purchase::from(quote)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Failed checks of a session upstream after which it is renewed without being checked.
+const MAX_VERIFY_FAILURES: u8 = 3;
+
+fn is_due(session: &Session, now: DateTime<Utc>, verify_lead: chrono::Duration) -> bool {
+    session.is_active()
+        && !session.paused
+        && (session.next_check <= now
+            || (!session.verified && session.next_check - verify_lead <= now))
+}
+
+pub(crate) async fn check_renewal<P: Provider>(
+    config: Arc<RwLock<Accounts>>,
+    locks: &Locks,
+    provider: &P,
+    clock: &impl Clock,
+    audit: &AuditLog,
+) {
+    let now = clock.now();
+    let (settings, names) = {
+        let config = config.read().await;
+        let verify_lead = chrono::Duration::minutes(config.settings.verify_lead);
+        let names: Vec<String> = config
+            .accounts
+            .iter()
+            .filter(|account| {
+                account
+                    .session
+                    .as_ref()
+                    .is_some_and(|session| is_due(session, now, verify_lead))
+            })
+            .map(|account| account.name.clone())
+            .collect();
+        (config.settings.clone(), names)
+    };
+    let verify_lead = chrono::Duration::minutes(settings.verify_lead);
+
+    for name in names {
+        let span = tracing::info_span!("renewal", id = %correlation_id(), account = %name);
+        async {
+            let Some(_guard) = locks.try_lock(&name) else {
+                log::info!("Account {} is busy, postponing its renewal", name);
+                return;
+            };
+            let Some((account, session)) = config
+                .read()
+                .await
+                .accounts
+                .iter()
+                .find(|a| a.name == name)
+                .and_then(|a| a.session.clone().map(|s| (a.clone(), s)))
+                .filter(|(_, session)| is_due(session, now, verify_lead))
+            else {
+                return;
+            };
+
+            let connection = match provider.connect(&account).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    let event = renewal_event(&account.name, &session, &settings);
+                    fail(audit, event, &account.name, e.as_ref());
+                    return;
+                }
+            };
+            let mut session =
+                renew(&connection, &account.name, session, &settings, now, audit).await;
+
+            if let Some(conf) = config
+                .write()
+                .await
+                .accounts
+                .iter_mut()
+                .find(|a| a.name == account.name)
+            {
+                if let (Some(session), Some(current)) = (&mut session, &conf.session) {
+                    session.paused = current.paused;
+                }
+                conf.session = session;
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+}
+
+fn publish_renewed(name: &str, expiry: DateTime<Utc>, session: &Session) {
+    EVENTS.publish(Update::SessionRenewed {
+        account: name.to_string(),
+        expiry,
+        plan: session.clone(),
+    });
+}
+
+fn renewal_event(name: &str, session: &Session, settings: &Settings) -> Event {
+    Event::new(
+        SCHEDULER,
+        "renew",
+        Some(name),
+        json!({ "target": session.target(), "dry_run": session.dry_run || settings.dry_run }),
+    )
+}
+
+fn fail(audit: &AuditLog, event: Event, name: &str, error: &(dyn Error + Send + Sync)) {
+    audit.record(event.failed(error.to_string()));
+    METRICS.renewal(name, "failed");
+    EVENTS.publish(Update::RenewalFailed {
+        account: name.to_string(),
+        error: error.to_string(),
+    });
+}
+
+async fn renew(
+    connection: &impl Connection,
+    name: &str,
+    mut session: Session,
+    settings: &Settings,
+    now: DateTime<Utc>,
+    audit: &AuditLog,
+) -> Option<Session> {
+    let dry_run = session.dry_run || settings.dry_run;
+    let event = renewal_event(name, &session, settings);
+    if dry_run && now < session.next_check {
+        session.verified = true;
+        return Some(session);
+    }
+    if !dry_run {
+        log::info!("Verifying session of account {}", name);
+        let live = connection.live_expiry().await;
+        if live.is_ok() {
+            session.verify_failures = 0;
+        }
+        match live {
+            Ok(Some(expiry)) => {
+                if expiry + chrono::Duration::minutes(1) >= session.target() {
+                    log::info!(
+                        "Session of account {} already covers {}, skipping renewal",
+                        name,
+                        session.target()
+                    );
+                    audit.record(event.succeeded(format!("Already covered until {}", expiry)));
+                    METRICS.renewal(name, "covered");
+                    session.reschedule(expiry);
+                    publish_renewed(name, expiry, &session);
+                    return Some(session);
+                }
+                if expiry > session.expected_expiry() {
+                    log::info!(
+                        "Session of account {} was extended upstream until {}",
+                        name,
+                        expiry
+                    );
+                    audit.record(event.succeeded(format!("Extended upstream until {}", expiry)));
+                    METRICS.renewal(name, "extended");
+                    session.reschedule(expiry);
+                    publish_renewed(name, expiry, &session);
+                    return Some(session);
+                }
+                if now < session.next_check {
+                    session.verified = true;
+                    return Some(session);
+                }
+            }
+            Ok(None) => {
+                if now < session.expected_expiry() {
+                    if settings.stop_on_external_stop {
+                        log::info!(
+                            "Session of account {} was stopped upstream, stopping renewal",
+                            name
+                        );
+                        audit.record(event.succeeded("Stopped upstream, renewal stopped"));
+                        METRICS.renewal(name, "stopped");
+                        EVENTS.publish(Update::PlanStopped {
+                            account: name.to_string(),
+                            reason: "Stopped upstream".to_string(),
+                        });
+                        return None;
+                    }
+                    log::info!(
+                        "Session of account {} was stopped upstream, renewal will continue",
+                        name
+                    );
+                }
+                if now < session.next_check {
+                    session.verified = true;
+                    return Some(session);
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to verify session of account {}: {:?}", name, e);
+                session.verify_failures = session.verify_failures.saturating_add(1);
+                // Retried on the next tick, as buying blindly could pay for a session extended
+                // upstream, until the car has been left unparked for too long.
+                let overdue = now - session.expected_expiry()
+                    >= chrono::Duration::minutes(MAX_VERIFY_FAILURES as i64);
+                if now < session.next_check
+                    || (session.verify_failures < MAX_VERIFY_FAILURES && !overdue)
+                {
+                    return Some(session);
+                }
+                log::warn!(
+                    "Session of account {} could not be verified {} times, renewing without it",
+                    name,
+                    session.verify_failures
+                );
+            }
+        }
+    }
+
+    log::info!("Renewing account {}", name);
+    match connection.buy(dry_run).await {
+        Ok(purchase) => {
+            audit.record(
+                event
+                    .succeeded(format!("Renewed until {}", purchase.expiry))
+                    .cost(purchase.cost, &purchase.currency),
+            );
+            session.reschedule(purchase.expiry);
+            publish_renewed(name, purchase.expiry, &session);
+            if dry_run {
+                METRICS.renewal(name, "dry_run");
+                log::info!(
+                    "Dry run: renewal of account {} simulated, next check at {}",
+                    name,
+                    session.next_check
+                );
+            } else {
+                METRICS.renewal(name, "renewed");
+                METRICS.spend(name, purchase.cost, &purchase.currency);
+                log::info!("Vehicle parked");
+            }
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            fail(audit, event, name, e.as_ref());
+        }
+    }
+    Some(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::simulation::SimulatedProvider;
+    use chrono::{Duration, TimeZone};
+
+    const PLATE: &str = "AA123BB";
+
+    struct Plan {
+        config: Arc<RwLock<Accounts>>,
+        clock: Arc<VirtualClock>,
+        provider: SimulatedProvider,
+        start: DateTime<Utc>,
+    }
+
+    impl Plan {
+        /// Plans the renewal of a car until `minutes` after the start, parked for 15 minutes at
+        /// the start when `parked`.
+        async fn new(minutes: i64, parked: bool, settings: Settings) -> Self {
+            let start = Utc.with_ymd_and_hms(2026, 1, 5, 8, 0, 0).unwrap();
+            let clock = Arc::new(VirtualClock::new(start));
+            let provider = SimulatedProvider::new(clock.clone(), 15, 1.5);
+            let mut accounts: Accounts = serde_yaml::from_str(&format!(
+                "accounts:
+  - name: car
+    plate: {}
+    lot: 1
+    pay_by_phone:
+      login: login
+      password: password
+      payment_account_id: ''",
+                PLATE
+            ))
+            .unwrap();
+            accounts.settings = settings;
+            if parked {
+                provider
+                    .connect(&accounts.accounts[0])
+                    .await
+                    .unwrap()
+                    .buy(false)
+                    .await
+                    .unwrap();
+            }
+            accounts.accounts[0].session = Some(Session::new(
+                start + Duration::minutes(15),
+                start + Duration::minutes(minutes),
+                false,
+            ));
+            Self {
+                config: Arc::new(RwLock::new(accounts)),
+                clock,
+                provider,
+                start,
+            }
+        }
+
+        fn at(&self, minutes: i64) -> DateTime<Utc> {
+            self.start + Duration::minutes(minutes)
+        }
+
+        /// Runs the scheduler `minutes` after the start.
+        async fn check_at(&self, minutes: i64) {
+            let elapsed = self.clock.now() - self.start;
+            self.clock.advance(Duration::minutes(minutes) - elapsed);
+            check_renewal(
+                self.config.clone(),
+                &Locks::default(),
+                &self.provider,
+                self.clock.as_ref(),
+                &AuditLog::disabled(),
+            )
+            .await;
+        }
+
+        /// Parks the car from the official app, for 15 minutes from now.
+        async fn park_upstream(&self) {
+            let account = self.config.read().await.accounts[0].clone();
+            let connection = self.provider.connect(&account).await.unwrap();
+            connection.buy(false).await.unwrap();
+        }
+
+        async fn session(&self) -> Option<Session> {
+            self.config.read().await.accounts[0].session.clone()
+        }
+
+        fn purchases(&self) -> Vec<Purchase> {
+            self.provider.purchases(PLATE)
+        }
+    }
+
+    #[tokio::test]
+    async fn renews_when_due() {
+        let plan = Plan::new(60, true, Settings::default()).await;
+        plan.check_at(15).await;
+        assert_eq!(plan.purchases().len(), 1);
+        plan.check_at(16).await;
+        let purchases = plan.purchases();
+        assert_eq!(purchases.len(), 2);
+        assert_eq!(purchases[1].start, plan.at(16));
+        assert_eq!(plan.session().await.unwrap().next_check, plan.at(32));
+    }
+
+    #[tokio::test]
+    async fn verifies_ahead_without_buying() {
+        let plan = Plan::new(60, true, Settings::default()).await;
+        plan.check_at(14).await;
+        assert_eq!(plan.purchases().len(), 1);
+        let session = plan.session().await.unwrap();
+        assert!(session.verified);
+        assert_eq!(session.next_check, plan.at(16));
+    }
+
+    #[tokio::test]
+    async fn skips_renewal_already_covered_upstream() {
+        let plan = Plan::new(20, true, Settings::default()).await;
+        plan.clock.advance(Duration::minutes(10));
+        plan.park_upstream().await;
+        plan.check_at(14).await;
+        plan.check_at(26).await;
+        assert_eq!(plan.purchases().len(), 2);
+        assert!(!plan.session().await.unwrap().is_active());
+    }
+
+    #[tokio::test]
+    async fn follows_extension_upstream() {
+        let plan = Plan::new(60, true, Settings::default()).await;
+        plan.clock.advance(Duration::minutes(10));
+        plan.park_upstream().await;
+        plan.check_at(14).await;
+        assert_eq!(plan.purchases().len(), 2);
+        let session = plan.session().await.unwrap();
+        assert_eq!(session.next_check, plan.at(26));
+        assert_eq!(session.target(), plan.at(60));
+        plan.check_at(26).await;
+        assert_eq!(plan.purchases().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_plan_stopped_upstream() {
+        let plan = Plan::new(60, false, Settings::default()).await;
+        plan.check_at(14).await;
+        assert!(plan.session().await.is_none());
+        plan.check_at(16).await;
+        assert!(plan.purchases().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_plan_stopped_upstream_when_configured() {
+        let settings = Settings {
+            stop_on_external_stop: false,
+            ..Settings::default()
+        };
+        let plan = Plan::new(60, false, settings).await;
+        plan.check_at(14).await;
+        assert!(plan.session().await.is_some());
+        plan.check_at(16).await;
+        assert_eq!(plan.purchases().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn verifies_with_configured_lead() {
+        let settings = Settings {
+            verify_lead: 5,
+            ..Settings::default()
+        };
+        let plan = Plan::new(60, true, settings).await;
+        plan.check_at(10).await;
+        assert!(!plan.session().await.unwrap().verified);
+        plan.check_at(11).await;
+        assert!(plan.session().await.unwrap().verified);
+        assert_eq!(plan.purchases().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dry_run_never_verifies_upstream() {
+        let settings = Settings {
+            dry_run: true,
+            ..Settings::default()
+        };
+        // Nothing was bought upstream, which would stop a real plan.
+        let plan = Plan::new(60, false, settings).await;
+        plan.check_at(14).await;
+        assert!(plan.session().await.unwrap().verified);
+        plan.check_at(16).await;
+        assert_eq!(plan.purchases().len(), 1);
+        assert_eq!(plan.session().await.unwrap().next_check, plan.at(32));
+    }
+
+    #[tokio::test]
+    async fn retries_failed_verification_before_buying() {
+        let plan = Plan::new(60, true, Settings::default()).await;
+        plan.provider.fail_checks(1);
+        plan.check_at(16).await;
+        assert_eq!(plan.purchases().len(), 1);
+        plan.check_at(17).await;
+        let purchases = plan.purchases();
+        assert_eq!(purchases.len(), 2);
+        assert_eq!(purchases[1].start, plan.at(17));
+    }
+
+    #[tokio::test]
+    async fn renews_unverified_after_repeated_failures() {
+        let plan = Plan::new(60, true, Settings::default()).await;
+        plan.provider.fail_checks(10);
+        plan.check_at(16).await;
+        plan.check_at(17).await;
+        assert_eq!(plan.purchases().len(), 1);
+        plan.check_at(18).await;
+        let purchases = plan.purchases();
+        assert_eq!(purchases.len(), 2);
+        assert_eq!(purchases[1].start, plan.at(18));
+        assert_eq!(plan.session().await.unwrap().verify_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn paused_plan_is_not_renewed() {
+        let plan = Plan::new(60, true, Settings::default()).await;
+        plan.config.write().await.accounts[0]
+            .session
+            .as_mut()
+            .unwrap()
+            .paused = true;
+        plan.check_at(16).await;
+        assert_eq!(plan.purchases().len(), 1);
+    }
+}
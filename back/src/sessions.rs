@@ -0,0 +1,203 @@
+use crate::middleware::hash_token;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::AppendHeaders;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+pub(crate) const SESSION_COOKIE: &str = "session";
+pub(crate) const REFRESH_COOKIE: &str = "refresh";
+
+const ACCESS_TTL: Duration = Duration::minutes(15);
+const REFRESH_TTL: Duration = Duration::days(7);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct LoginSession {
+    pub(crate) id: String,
+    pub(crate) user: String,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) expires: DateTime<Utc>,
+}
+
+struct Entry {
+    session: LoginSession,
+    access_hash: String,
+    access_expires: DateTime<Utc>,
+    refresh_hash: String,
+}
+
+pub(crate) struct Tokens {
+    access: String,
+    refresh: String,
+    pub(crate) session: LoginSession,
+}
+
+pub(crate) struct Sessions {
+    entries: Mutex<Vec<Entry>>,
+    secure: bool,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub(crate) fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let mut salt = [0u8; 16];
+    rand::rng().fill_bytes(&mut salt);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::encode_b64(&salt)?)?
+        .to_string())
+}
+
+pub(crate) fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            log::error!("Invalid password hash: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Returns the value of a cookie sent with the request.
+pub(crate) fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+impl Sessions {
+    pub(crate) fn new(secure: bool) -> Self {
+        Self {
+            entries: Mutex::new(Vec::new()),
+            secure,
+        }
+    }
+
+    pub(crate) fn create(&self, user: &str) -> Tokens {
+        let now = Utc::now();
+        let tokens = Tokens {
+            access: random_token(),
+            refresh: random_token(),
+            session: LoginSession {
+                id: random_token()[..16].to_string(),
+                user: user.to_string(),
+                created: now,
+                expires: now + REFRESH_TTL,
+            },
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.session.expires > now);
+        entries.push(Entry {
+            session: tokens.session.clone(),
+            access_hash: hash_token(&tokens.access),
+            access_expires: now + ACCESS_TTL,
+            refresh_hash: hash_token(&tokens.refresh),
+        });
+        tokens
+    }
+
+    /// Returns the name of the user owning a valid access token.
+    pub(crate) fn authenticate(&self, access: &str) -> Option<String> {
+        let hash = hash_token(access);
+        let now = Utc::now();
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.access_hash == hash && e.access_expires > now)
+            .map(|e| e.session.user.clone())
+    }
+
+    /// Exchanges a refresh token for new access and refresh tokens, invalidating the previous ones.
+    pub(crate) fn refresh(&self, refresh: &str) -> Option<Tokens> {
+        let hash = hash_token(refresh);
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.session.expires > now);
+        let entry = entries.iter_mut().find(|e| e.refresh_hash == hash)?;
+        let tokens = Tokens {
+            access: random_token(),
+            refresh: random_token(),
+            session: LoginSession {
+                expires: now + REFRESH_TTL,
+                ..entry.session.clone()
+            },
+        };
+        entry.session = tokens.session.clone();
+        entry.access_hash = hash_token(&tokens.access);
+        entry.access_expires = now + ACCESS_TTL;
+        entry.refresh_hash = hash_token(&tokens.refresh);
+        Some(tokens)
+    }
+
+    pub(crate) fn list(&self) -> Vec<LoginSession> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.session.expires > now);
+        entries.iter().map(|e| e.session.clone()).collect()
+    }
+
+    pub(crate) fn revoke(&self, id: &str) -> Option<LoginSession> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries.iter().position(|e| e.session.id == id)?;
+        Some(entries.remove(index).session)
+    }
+
+    /// Revokes the session an access or refresh token belongs to.
+    pub(crate) fn revoke_token(&self, token: &str) -> Option<LoginSession> {
+        let hash = hash_token(token);
+        let id = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.access_hash == hash || e.refresh_hash == hash)
+            .map(|e| e.session.id.clone())?;
+        self.revoke(&id)
+    }
+
+    fn set_cookie(
+        &self,
+        name: &str,
+        value: &str,
+        max_age: i64,
+    ) -> (header::HeaderName, HeaderValue) {
+        let secure = if self.secure { "; Secure" } else { "" };
+        let cookie = format!(
+            "{}={}; Path=/api; Max-Age={}; HttpOnly; SameSite=Strict{}",
+            name, value, max_age, secure
+        );
+        (header::SET_COOKIE, HeaderValue::from_str(&cookie).unwrap())
+    }
+
+    pub(crate) fn cookies(
+        &self,
+        tokens: &Tokens,
+    ) -> AppendHeaders<[(header::HeaderName, HeaderValue); 2]> {
+        AppendHeaders([
+            self.set_cookie(SESSION_COOKIE, &tokens.access, ACCESS_TTL.num_seconds()),
+            self.set_cookie(REFRESH_COOKIE, &tokens.refresh, REFRESH_TTL.num_seconds()),
+        ])
+    }
+
+    pub(crate) fn clear_cookies(&self) -> AppendHeaders<[(header::HeaderName, HeaderValue); 2]> {
+        AppendHeaders([
+            self.set_cookie(SESSION_COOKIE, "", 0),
+            self.set_cookie(REFRESH_COOKIE, "", 0),
+        ])
+    }
+}
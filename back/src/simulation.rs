@@ -0,0 +1,200 @@
+use crate::audit::AuditLog;
+use crate::clock::{Clock, VirtualClock};
+use crate::config::{Accounts, Config, Session};
+use crate::locks::Locks;
+use crate::scheduler::{check_renewal, Connection, Provider, Purchase};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+
+#[derive(Clone)]
+pub(crate) struct SimulatedProvider {
+    clock: Arc<VirtualClock>,
+    unit: Duration,
+    cost: f64,
+    purchases: Arc<Mutex<Vec<(String, Purchase)>>>,
+    /// Number of upcoming checks of the live session that fail.
+    failing_checks: Arc<Mutex<u32>>,
+}
+
+pub(crate) struct SimulatedConnection {
+    plate: String,
+    provider: SimulatedProvider,
+}
+
+impl SimulatedProvider {
+    pub(crate) fn new(clock: Arc<VirtualClock>, unit: i64, cost: f64) -> Self {
+        Self {
+            clock,
+            unit: Duration::minutes(unit),
+            cost,
+            purchases: Arc::new(Mutex::new(Vec::new())),
+            failing_checks: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Makes the next `count` checks of the live session fail, as when PayByPhone is unreachable.
+    #[cfg(test)]
+    pub(crate) fn fail_checks(&self, count: u32) {
+        *self.failing_checks.lock().unwrap() = count;
+    }
+
+    pub(crate) fn purchases(&self, plate: &str) -> Vec<Purchase> {
+        self.purchases
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(p, _)| p == plate)
+            .map(|(_, purchase)| purchase.clone())
+            .collect()
+    }
+}
+
+impl Provider for SimulatedProvider {
+    type Connection = SimulatedConnection;
+
+    async fn connect(
+        &self,
+        account: &Config,
+    ) -> Result<SimulatedConnection, Box<dyn Error + Send + Sync>> {
+        Ok(SimulatedConnection {
+            plate: account.plate.clone(),
+            provider: self.clone(),
+        })
+    }
+}
+
+impl Connection for SimulatedConnection {
+    async fn live_expiry(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error + Send + Sync>> {
+        {
+            let mut failing = self.provider.failing_checks.lock().unwrap();
+            if *failing > 0 {
+                *failing -= 1;
+                return Err("PayByPhone is unreachable".into());
+            }
+        }
+        let now = self.provider.clock.now();
+        Ok(self
+            .provider
+            .purchases(&self.plate)
+            .last()
+            .map(|purchase| purchase.expiry)
+            .filter(|expiry| *expiry > now))
+    }
+
+    async fn buy(&self, _dry_run: bool) -> Result<Purchase, Box<dyn Error + Send + Sync>> {
+        let start = self.provider.clock.now();
+        let purchase = Purchase {
+            start,
+            expiry: start + self.provider.unit,
+            cost: self.provider.cost,
+            currency: "EUR".to_string(),
+        };
+        self.provider
+            .purchases
+            .lock()
+            .unwrap()
+            .push((self.plate.clone(), purchase.clone()));
+        Ok(purchase)
+    }
+}
+
+pub(crate) async fn run(
+    mut accounts: Accounts,
+    account: Option<String>,
+    duration: i16,
+    days: i64,
+    unit: i64,
+    cost: f64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let start = Utc::now().duration_trunc(Duration::minutes(1))?;
+    let end = start + Duration::days(days);
+    let clock = Arc::new(VirtualClock::new(start));
+    let provider = SimulatedProvider::new(clock.clone(), unit, cost);
+
+    accounts
+        .accounts
+        .retain(|a| account.as_ref().is_none_or(|name| &a.name == name));
+    if accounts.accounts.is_empty() {
+        return Err(Box::from("Account not found"));
+    }
+
+    let mut targets = Vec::new();
+    for account in accounts.accounts.iter_mut() {
+        let purchase = provider.connect(account).await?.buy(false).await?;
+        let target = purchase.start + Duration::minutes(duration as i64);
+        account.session = Some(Session::new(purchase.expiry, target, false));
+        targets.push((account.name.clone(), account.plate.clone(), target));
+    }
+    let config = Arc::new(RwLock::new(accounts));
+    let locks = Locks::default();
+    let audit = AuditLog::disabled();
+
+    while clock.now() < end {
+        check_renewal(config.clone(), &locks, &provider, clock.as_ref(), &audit).await;
+        clock.advance(Duration::minutes(1));
+    }
+
+    println!(
+        "Simulated {} days from {} with {} minute purchases at {:.2} EUR",
+        days,
+        start.format("%Y-%m-%d %H:%M"),
+        unit,
+        cost
+    );
+    for (name, plate, target) in targets {
+        let purchases = provider.purchases(&plate);
+        println!();
+        println!(
+            "Account {} ({}), plan until {}",
+            name,
+            plate,
+            target.format("%Y-%m-%d %H:%M")
+        );
+
+        let mut gaps = Duration::zero();
+        let mut gap_count = 0;
+        let mut previous: Option<&Purchase> = None;
+        for purchase in purchases.iter() {
+            if let Some(previous) = previous {
+                if purchase.start > previous.expiry {
+                    let gap = purchase.start - previous.expiry;
+                    println!(
+                        "  gap of {} minutes from {} to {}",
+                        gap.num_minutes(),
+                        previous.expiry.format("%Y-%m-%d %H:%M"),
+                        purchase.start.format("%Y-%m-%d %H:%M")
+                    );
+                    gaps += gap;
+                    gap_count += 1;
+                }
+            }
+            println!(
+                "  {} -> {}  {:.2} {}",
+                purchase.start.format("%Y-%m-%d %H:%M"),
+                purchase.expiry.format("%Y-%m-%d %H:%M"),
+                purchase.cost,
+                purchase.currency
+            );
+            previous = Some(purchase);
+        }
+        if let Some(last) = previous {
+            if last.expiry < target && target <= end {
+                println!(
+                    "  plan not fulfilled: covered until {}",
+                    last.expiry.format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+
+        println!(
+            "Total: {} purchases, {:.2} EUR, {} gaps totalling {} minutes",
+            purchases.len(),
+            purchases.iter().map(|p| p.cost).sum::<f64>(),
+            gap_count,
+            gaps.num_minutes()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use crate::config::{Accounts, Session};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+pub(crate) struct Store {
+    path: PathBuf,
+}
+
+impl Store {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub(crate) fn load(&self) -> Result<HashMap<String, Session>, Box<dyn std::error::Error>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(sessions) => Ok(sessions),
+                Err(e) => Err(Box::new(e)),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub(crate) fn restore(
+        &self,
+        accounts: &mut Accounts,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut sessions = self.load()?;
+        for account in accounts.accounts.iter_mut() {
+            account.session = sessions.remove(&account.name);
+            if account.session.is_some() {
+                log::debug!("Restored session of account {}", account.name);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn save(&self, accounts: &Accounts) -> Result<(), Box<dyn std::error::Error>> {
+        let sessions: HashMap<&String, &Session> = accounts
+            .accounts
+            .iter()
+            .filter_map(|a| a.session.as_ref().map(|s| (&a.name, s)))
+            .collect();
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&sessions)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config::{Config, Session};
+use crate::paybyphone::PayByPhone;
+use crate::types::ParkingSession;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::task::JoinSet;
+use utoipa::ToSchema;
+
+/// How long the upstream session of an account is served from the cache before being fetched again.
+const STATUS_TTL: Duration = Duration::seconds(30);
+
+/// Last known upstream state of an account.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+struct Live {
+    session: Option<ParkingSession>,
+    /// Error of the last fetch, when it failed. The session is then the one fetched before it.
+    error: Option<String>,
+    /// When the session was last fetched successfully.
+    fetched: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    #[schema(ignore)]
+    checked: Option<DateTime<Utc>>,
+    /// Next check of the renewal plan when the session was fetched, which changes when it is renewed.
+    #[serde(skip)]
+    #[schema(ignore)]
+    plan_check: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct AccountStatus {
+    name: String,
+    plate: String,
+    lot: i32,
+    plan: Option<Session>,
+    unmanaged: Option<ParkingSession>,
+    #[serde(flatten)]
+    live: Live,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Status {
+    generated: DateTime<Utc>,
+    accounts: Vec<AccountStatus>,
+}
+
+/// Caches the upstream session of every account, so that the status of all of them can be served
+/// without logging in to PayByPhone for each request.
+#[derive(Default)]
+pub(crate) struct StatusCache {
+    entries: Mutex<HashMap<String, Live>>,
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+async fn fetch(account: &Config) -> Result<Option<ParkingSession>, String> {
+    match PayByPhone::connect(account).await {
+        Ok(pay_by_phone) => pay_by_phone
+            .current_session()
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl StatusCache {
+    /// Whether the session of an account was not checked recently, or was changed by a parking or
+    /// a renewal since.
+    fn stale(&self, account: &Config, now: DateTime<Utc>) -> bool {
+        let plan_check = account.session.as_ref().map(|s| s.next_check);
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&account.name)
+            .is_none_or(|live| {
+                live.plan_check != plan_check
+                    || live
+                        .checked
+                        .is_none_or(|checked| now - checked >= STATUS_TTL)
+            })
+    }
+
+    /// Fetches concurrently the sessions of the accounts not checked recently. Concurrent callers
+    /// wait for the refresh in progress instead of fetching the same accounts again.
+    async fn refresh(&self, accounts: &[Config]) {
+        let _refreshing = self.refreshing.lock().await;
+        let now = Utc::now();
+        let mut fetches = JoinSet::new();
+        for account in accounts.iter().filter(|a| self.stale(a, now)) {
+            let account = account.clone();
+            fetches.spawn(async move {
+                let plan_check = account.session.as_ref().map(|s| s.next_check);
+                (account.name.clone(), plan_check, fetch(&account).await)
+            });
+        }
+        while let Some(result) = fetches.join_next().await {
+            let (name, plan_check, result) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    log::error!("Status fetch panicked: {:?}", e);
+                    continue;
+                }
+            };
+            let mut entries = self.entries.lock().unwrap();
+            let live = entries.entry(name).or_default();
+            live.checked = Some(now);
+            live.plan_check = plan_check;
+            match result {
+                Ok(session) => {
+                    live.session = session;
+                    live.error = None;
+                    live.fetched = Some(now);
+                }
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    live.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Returns the status of the accounts, with their upstream sessions at most `STATUS_TTL` old.
+    pub(crate) async fn status(&self, accounts: Vec<Config>) -> Status {
+        self.refresh(&accounts).await;
+        let entries = self.entries.lock().unwrap();
+        Status {
+            generated: Utc::now(),
+            accounts: accounts
+                .into_iter()
+                .map(|account| AccountStatus {
+                    live: entries.get(&account.name).cloned().unwrap_or_default(),
+                    name: account.name,
+                    plate: account.plate,
+                    lot: account.lot,
+                    plan: account.session,
+                    unmanaged: account.unmanaged,
+                })
+                .collect(),
+        }
+    }
+}
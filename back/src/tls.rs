@@ -0,0 +1,165 @@
+use axum::serve::Listener;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// How often the certificate and key files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+/// Time a client is given to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections handshaken but not accepted yet by the server.
+const BACKLOG: usize = 64;
+
+/// Certificate and private key of the HTTPS listener, reloaded when their files change.
+pub(crate) struct Tls {
+    cert: PathBuf,
+    key: PathBuf,
+    acceptor: RwLock<TlsAcceptor>,
+    modified: Mutex<(SystemTime, SystemTime)>,
+}
+
+fn modified(cert: &Path, key: &Path) -> io::Result<(SystemTime, SystemTime)> {
+    Ok((
+        fs::metadata(cert)?.modified()?,
+        fs::metadata(key)?.modified()?,
+    ))
+}
+
+fn load(cert: &Path, key: &Path) -> Result<TlsAcceptor, Box<dyn Error + Send + Sync>> {
+    let certs = CertificateDer::pem_slice_iter(&fs::read(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate {}: {}", cert.display(), e))?;
+    let key = PrivateKeyDer::from_pem_slice(&fs::read(key)?)
+        .map_err(|e| format!("Invalid private key {}: {}", key.display(), e))?;
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+impl Tls {
+    pub(crate) fn new(
+        cert: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+    ) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
+        let (cert, key) = (cert.into(), key.into());
+        let modified = modified(&cert, &key)?;
+        let acceptor = load(&cert, &key)?;
+        Ok(Arc::new(Self {
+            cert,
+            key,
+            acceptor: RwLock::new(acceptor),
+            modified: Mutex::new(modified),
+        }))
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Loads the certificate and key again if their files changed since they were last loaded. A
+    /// broken pair, e.g. while they are being replaced, is logged and the previous one kept.
+    fn reload(&self) {
+        let modified = match modified(&self.cert, &self.key) {
+            Ok(modified) => modified,
+            Err(e) => {
+                log::error!("Failed to check the TLS certificate: {:?}", e);
+                return;
+            }
+        };
+        if *self.modified.lock().unwrap() == modified {
+            return;
+        }
+        match load(&self.cert, &self.key) {
+            Ok(acceptor) => {
+                *self.acceptor.write().unwrap() = acceptor;
+                *self.modified.lock().unwrap() = modified;
+                log::info!("Reloaded TLS certificate {}", self.cert.display());
+            }
+            Err(e) => log::error!("Failed to reload TLS certificate: {}", e),
+        }
+    }
+
+    /// Checks the certificate and key files for changes in the background.
+    pub(crate) fn watch(self: &Arc<Self>) {
+        let tls = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RELOAD_INTERVAL).await;
+                tls.reload();
+            }
+        });
+    }
+}
+
+/// Listener accepting TLS connections. Handshakes run concurrently, so that a slow client never
+/// delays the others.
+pub(crate) struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub(crate) fn new(listener: TcpListener, tls: Arc<Tls>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(BACKLOG);
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        log::error!("Failed to accept connection: {:?}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let acceptor = tls.acceptor();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => log::debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => log::debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The accept loop only stops once the listener is dropped.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
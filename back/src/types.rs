@@ -1,7 +1,8 @@
 use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub(crate) struct RateOption {
     #[serde(rename = "rateOptionId")]
     rate_option_id: String,
@@ -9,7 +10,7 @@ pub(crate) struct RateOption {
     r#type: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub(crate) struct Segment {
     #[serde(rename = "chargeableTimeUnitType")]
     chargeable_time_unit_type: i32,
@@ -27,13 +28,13 @@ pub(crate) struct Segment {
     parking_start: DateTime<Utc>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub(crate) struct TotalCost {
     amount: f64,
     currency: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub(crate) struct ParkedVehicle {
     #[serde(rename = "countryCode")]
     country_code: String,
@@ -45,12 +46,12 @@ pub(crate) struct ParkedVehicle {
     r#type: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub(crate) struct ParkingSession {
     #[serde(rename = "couponApplied")]
     coupon_applied: Option<String>,
     #[serde(rename = "expireTime")]
-    expire_time: String,
+    pub(crate) expire_time: String,
     #[serde(rename = "fpsApplies")]
     fps_applies: bool,
     #[serde(rename = "isExtendable")]
@@ -78,7 +79,7 @@ pub(crate) struct ParkingSession {
     pub(crate) vehicle: ParkedVehicle,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Vehicle {
     #[serde(rename = "vehicleId")]
     vehicle_id: String,
@@ -92,7 +93,7 @@ pub struct Vehicle {
     r#type: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Quote {
     #[serde(rename = "locationId")]
     location_id: String,
@@ -100,7 +101,7 @@ pub struct Quote {
     #[serde(rename = "quoteDate")]
     quote_date: DateTime<Utc>,
     #[serde(rename = "totalCost")]
-    total_cost: Cost,
+    pub(crate) total_cost: Cost,
     #[serde(rename = "parkingAccountId")]
     parking_account_id: String,
     #[serde(rename = "parkingStartTime")]
@@ -120,13 +121,13 @@ pub struct Quote {
     profile: Profile,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Cost {
-    amount: f64,
-    currency: String,
+    pub(crate) amount: f64,
+    pub(crate) currency: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct QuoteItem {
     #[serde(rename = "quoteItemType")]
     quote_item_type: String,
@@ -137,7 +138,7 @@ pub struct QuoteItem {
     sub_quote_items: Option<Vec<SubQuoteItem>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct SubQuoteItem {
     #[serde(rename = "quoteItemType")]
     quote_item_type: String,
@@ -146,7 +147,7 @@ pub struct SubQuoteItem {
     cost_amount: Cost,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Profile {
     #[serde(rename = "profileName")]
     profile_name: String,
@@ -155,7 +156,7 @@ pub struct Profile {
     user_messages: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct ParkingOption {
     name: String,
     r#type: String,
@@ -194,14 +195,14 @@ pub struct ParkingOption {
     profile: Profile,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Duration {
     pub(crate) quantity: i16,
     #[serde(rename = "timeUnit")]
     pub(crate) time_unit: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct RestrictionPeriod {
     #[serde(rename = "startTime")]
     start_time: DateTime<FixedOffset>,
@@ -211,20 +212,20 @@ pub struct RestrictionPeriod {
     pub(crate) max_stay: Duration,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct AvailableTimeUnitsWithRestrictions {
     minutes: TimeUnitRestriction,
     hours: TimeUnitRestriction,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct TimeUnitRestriction {
     duration: Duration,
     #[serde(rename = "endTime")]
     end_time: DateTime<FixedOffset>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct RenewalParking {
     #[serde(rename = "isAllowed")]
     is_allowed: bool,
@@ -0,0 +1,567 @@
+use crate::audit::{AuditLog, Filter};
+use crate::config::{Accounts, Config, Role, Session, User};
+use crate::estimate::{estimate, Estimate};
+use crate::locks::Locks;
+use crate::middleware::authorize;
+use crate::paybyphone::PURCHASE_MINUTES;
+use crate::ratelimit::{upstream_limit, RateLimits};
+use crate::state::Store;
+use crate::{
+    adopt, get_vehicles, initalize_pay_by_phone, park, pause, resume, AccountName, Adoption,
+    AppState, Parking,
+};
+use axum::body::to_bytes;
+use axum::extract::{Extension, Path, Query, Request, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::{OpenApiRouter, UtoipaMethodRouterExt};
+use utoipa_axum::routes;
+
+/// Body of every error response of the versioned API.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ApiError {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorDetail {
+    /// HTTP status code of the response.
+    status: u16,
+    message: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewSession {
+    /// Minutes the session is renewed for, from now.
+    duration: i16,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewPlan {
+    /// When auto-renewal stops.
+    end: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PlanChange {
+    paused: bool,
+}
+
+/// End of a quoted parking, given either as a time or as minutes from now.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct QuoteTarget {
+    end: Option<DateTime<Utc>>,
+    duration: Option<i16>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct History {
+    action: Option<String>,
+    since: Option<DateTime<Utc>>,
+    /// Number of events returned, the most recent ones. Defaults to 100.
+    limit: Option<usize>,
+}
+
+pub(crate) fn router(limits: &Arc<RateLimits>) -> OpenApiRouter<AppState> {
+    let upstream = from_fn_with_state(limits.clone(), upstream_limit);
+    OpenApiRouter::new()
+        .routes(routes!(get_accounts))
+        .routes(routes!(get_account))
+        .routes(routes!(get_session, post_session).layer(upstream.clone()))
+        .routes(routes!(get_plan, patch_plan))
+        .routes(routes!(post_plan).layer(upstream.clone()))
+        .routes(routes!(get_account_quote).layer(upstream.clone()))
+        .routes(routes!(get_account_vehicles).layer(upstream.clone()))
+        .routes(routes!(get_history))
+        .routes(routes!(get_rate_options).layer(upstream))
+}
+
+/// Wraps the error responses of the versioned API in an `ApiError`, whether they come from a
+/// handler, an extractor or a middleware.
+pub(crate) async fn error_envelope(req: Request, next: Next) -> Response {
+    if !req.uri().path().starts_with("/v1/") {
+        return next.run(req).await;
+    }
+    let response = next.run(req).await;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let bytes = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    let message = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::String(message)) => message,
+        _ if !bytes.is_empty() => String::from_utf8_lossy(&bytes).trim().to_string(),
+        _ => status.canonical_reason().unwrap_or_default().to_string(),
+    };
+    let mut response = (
+        status,
+        Json(ApiError {
+            error: ErrorDetail {
+                status: status.as_u16(),
+                message,
+            },
+        }),
+    )
+        .into_response();
+    for (name, value) in parts.headers.iter() {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            response.headers_mut().insert(name, value.clone());
+        }
+    }
+    response
+}
+
+/// Checks that the user can act on the account as `role`, and that the account exists.
+async fn account(
+    config: &RwLock<Accounts>,
+    user: &User,
+    name: &str,
+    role: Role,
+) -> Result<Config, Response> {
+    if let Err(status) = authorize(user, role, Some(name)) {
+        return Err((status, Json("Forbidden")).into_response());
+    }
+    match config.read().await.accounts.iter().find(|a| a.name == name) {
+        Some(account) => Ok(account.clone()),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(format!("No account named {}", name)),
+        )
+            .into_response()),
+    }
+}
+
+/// Reports the failures of PayByPhone as such, rather than as failures of the backend.
+fn upstream(response: Response) -> Response {
+    match response.status() {
+        StatusCode::INTERNAL_SERVER_ERROR => {
+            let (mut parts, body) = response.into_parts();
+            parts.status = StatusCode::BAD_GATEWAY;
+            Response::from_parts(parts, body)
+        }
+        _ => response,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts",
+    tag = "accounts",
+    responses(
+        (status = 200, description = "The accounts the user has access to", body = Vec<Config>),
+        (status = 401, description = "Not authenticated", body = ApiError),
+    )
+)]
+async fn get_accounts(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+) -> Response {
+    let accounts: Vec<Config> = config
+        .read()
+        .await
+        .accounts
+        .iter()
+        .filter(|a| user.can_access(&a.name))
+        .cloned()
+        .collect();
+    (StatusCode::OK, Json(accounts)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}",
+    tag = "accounts",
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The account", body = Config),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account", body = ApiError),
+    )
+)]
+async fn get_account(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+) -> Response {
+    match account(&config, &user, &name, Role::Viewer).await {
+        Ok(account) => (StatusCode::OK, Json(account)).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/session",
+    tag = "parking",
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The live PayByPhone session of the account", body = crate::types::ParkingSession),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account, or no active session", body = ApiError),
+        (status = 502, description = "PayByPhone failed", body = ApiError),
+    )
+)]
+async fn get_session(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Viewer).await {
+        return response;
+    }
+    let session = match initalize_pay_by_phone(config, name).await {
+        Ok(pay_by_phone) => pay_by_phone.current_session().await,
+        Err(e) => Err(e),
+    };
+    match session {
+        Ok(Some(session)) => (StatusCode::OK, Json(session)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json("No active parking session")).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            (StatusCode::BAD_GATEWAY, Json(e.to_string())).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/accounts/{name}/session",
+    tag = "parking",
+    request_body = NewSession,
+    params(
+        ("name" = String, Path, description = "Name of the account"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Key replaying the response of a previous successful request with the same body"),
+    ),
+    responses(
+        (status = 201, description = "Parked, auto-renewal planned", body = crate::types::Quote),
+        (status = 200, description = "Dry run, the quote was not paid", body = crate::types::Quote),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account", body = ApiError),
+        (status = 409, description = "Another operation is in progress for the account", body = ApiError),
+        (status = 422, description = "The Idempotency-Key was used for another request", body = ApiError),
+        (status = 502, description = "PayByPhone failed", body = ApiError),
+    )
+)]
+async fn post_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(session): Json<NewSession>,
+) -> Response {
+    if let Err(response) = account(&state.config, &user, &name, Role::Parker).await {
+        return response;
+    }
+    let parking = Parking {
+        name,
+        duration: session.duration,
+        dry_run: session.dry_run,
+    };
+    let response = park(State(state), Extension(user), headers, Json(parking))
+        .await
+        .into_response();
+    match response.status() {
+        StatusCode::ACCEPTED => {
+            let (mut parts, body) = response.into_parts();
+            parts.status = StatusCode::CREATED;
+            Response::from_parts(parts, body)
+        }
+        _ => upstream(response),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/plan",
+    tag = "renewal",
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The renewal plan of the account", body = Session),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account, or no renewal plan", body = ApiError),
+    )
+)]
+async fn get_plan(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+) -> Response {
+    match account(&config, &user, &name, Role::Viewer).await {
+        Ok(Config {
+            session: Some(session),
+            ..
+        }) => (StatusCode::OK, Json(session)).into_response(),
+        Ok(_) => (StatusCode::NOT_FOUND, Json("No renewal plan")).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/accounts/{name}/plan",
+    tag = "renewal",
+    request_body = NewPlan,
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The renewal plan attached to the session started outside of the app", body = Session),
+        (status = 400, description = "The end time is out of range, or already covered by the session", body = ApiError),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account, or no active session", body = ApiError),
+        (status = 409, description = "Another operation is in progress for the account", body = ApiError),
+        (status = 502, description = "PayByPhone failed", body = ApiError),
+    )
+)]
+async fn post_plan(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(store): State<Arc<Store>>,
+    State(locks): State<Arc<Locks>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    Json(plan): Json<NewPlan>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Parker).await {
+        return response;
+    }
+    let adoption = Adoption {
+        name,
+        end: plan.end,
+    };
+    upstream(
+        adopt(
+            State(config),
+            State(store),
+            State(locks),
+            State(audit),
+            Extension(user),
+            Json(adoption),
+        )
+        .await
+        .into_response(),
+    )
+}
+
+#[utoipa::path(
+    patch,
+    path = "/accounts/{name}/plan",
+    tag = "renewal",
+    request_body = PlanChange,
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The paused or resumed renewal plan", body = Session),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account, or no renewal plan", body = ApiError),
+    )
+)]
+async fn patch_plan(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(store): State<Arc<Store>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    Json(change): Json<PlanChange>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Parker).await {
+        return response;
+    }
+    let account_name = Json(AccountName { name });
+    if change.paused {
+        pause(
+            State(config),
+            State(store),
+            State(audit),
+            Extension(user),
+            account_name,
+        )
+        .await
+        .into_response()
+    } else {
+        resume(
+            State(config),
+            State(store),
+            State(audit),
+            Extension(user),
+            account_name,
+        )
+        .await
+        .into_response()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/quote",
+    tag = "parking",
+    params(("name" = String, Path, description = "Name of the account"), QuoteTarget),
+    responses(
+        (status = 200, description = "Purchases made to park until the end, renewals included, priced at the current rate", body = Estimate),
+        (status = 400, description = "Neither or both of end and duration, or an end in the past or too far", body = ApiError),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account", body = ApiError),
+        (status = 502, description = "PayByPhone failed", body = ApiError),
+    )
+)]
+async fn get_account_quote(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    Query(target): Query<QuoteTarget>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Viewer).await {
+        return response;
+    }
+    let now = Utc::now();
+    let target = match (target.end, target.duration) {
+        (Some(end), None) => end,
+        (None, Some(duration)) => now + chrono::Duration::minutes(duration as i64),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json("Exactly one of end and duration is required"),
+            )
+                .into_response()
+        }
+    };
+    if !Session::can_plan(now, target) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(format!("Cannot park until {}", target)),
+        )
+            .into_response();
+    }
+    let quote = match initalize_pay_by_phone(config, name).await {
+        Ok(pay_by_phone) => pay_by_phone.quote(PURCHASE_MINUTES).await,
+        Err(e) => Err(e),
+    };
+    match quote {
+        Ok(quote) => (StatusCode::OK, Json(estimate(&quote, target))).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            (StatusCode::BAD_GATEWAY, Json(e.to_string())).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/vehicles",
+    tag = "accounts",
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The vehicles of the PayByPhone account", body = Vec<crate::types::Vehicle>),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account", body = ApiError),
+        (status = 502, description = "PayByPhone failed", body = ApiError),
+    )
+)]
+async fn get_account_vehicles(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Viewer).await {
+        return response;
+    }
+    upstream(
+        get_vehicles(State(config), Extension(user), Query(AccountName { name }))
+            .await
+            .into_response(),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/history",
+    tag = "accounts",
+    params(("name" = String, Path, description = "Name of the account"), History),
+    responses(
+        (status = 200, description = "The most recent audit events of the account, oldest first", body = Vec<crate::audit::Event>),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account", body = ApiError),
+        (status = 500, description = "The audit log could not be read", body = ApiError),
+    )
+)]
+async fn get_history(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    State(audit): State<Arc<AuditLog>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+    Query(history): Query<History>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Viewer).await {
+        return response;
+    }
+    let filter = Filter {
+        principal: None,
+        account: Some(name),
+        action: history.action,
+        method: None,
+        route: None,
+        since: history.since,
+        limit: history.limit,
+    };
+    match audit.query(&filter) {
+        Ok(events) => (StatusCode::OK, Json(events)).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/accounts/{name}/rate-options",
+    tag = "parking",
+    params(("name" = String, Path, description = "Name of the account")),
+    responses(
+        (status = 200, description = "The rate options of the parking lot of the account", body = Vec<crate::types::ParkingOption>),
+        (status = 401, description = "Not authenticated", body = ApiError),
+        (status = 403, description = "Not allowed on this account", body = ApiError),
+        (status = 404, description = "No such account", body = ApiError),
+        (status = 502, description = "PayByPhone failed", body = ApiError),
+    )
+)]
+async fn get_rate_options(
+    State(config): State<Arc<RwLock<Accounts>>>,
+    Extension(user): Extension<User>,
+    Path(name): Path<String>,
+) -> Response {
+    if let Err(response) = account(&config, &user, &name, Role::Viewer).await {
+        return response;
+    }
+    let options = match initalize_pay_by_phone(config, name).await {
+        Ok(pay_by_phone) => pay_by_phone.get_rate_option().await,
+        Err(e) => Err(e),
+    };
+    match options {
+        Ok(options) => (StatusCode::OK, Json(options)).into_response(),
+        Err(e) => {
+            log::error!("{:?}", e);
+            (StatusCode::BAD_GATEWAY, Json(e.to_string())).into_response()
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::types::ApiError;
+
+/// Sends the session cookies of the backend along with a request.
+pub(crate) trait WithCredentials {
+    fn with_credentials(self) -> Self;
+}
+
+impl WithCredentials for reqwest::RequestBuilder {
+    fn with_credentials(self) -> Self {
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.fetch_credentials_include()
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self
+        }
+    }
+}
+
+/// Resolves an API URL relative to the page, such as `/api/` when the backend serves the frontend.
+pub(crate) fn resolve_url(url: &str) -> String {
+    #[cfg(target_arch = "wasm32")]
+    if url.starts_with('/') {
+        if let Some(origin) = web_sys::window().and_then(|window| window.location().origin().ok()) {
+            return format!("{}{}", origin, url);
+        }
+    }
+    url.to_string()
+}
+
+/// Message of an error response of the v1 API, or its status when it has none.
+pub(crate) async fn error_message(res: reqwest::Response) -> String {
+    let status = res.status();
+    match res.json::<ApiError>().await {
+        Ok(e) => e.error.message,
+        Err(_) => status.to_string(),
+    }
+}
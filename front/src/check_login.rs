@@ -1,29 +1,49 @@
+use crate::api::WithCredentials;
 use crate::routes::Route;
 use dioxus::prelude::navigator;
 use dioxus_logger::tracing::{error, info};
 
-pub(crate) async fn check_login(bearer: String, api_url: String) {
-    info!("Bearer token: {}", bearer);
-    let nav = navigator();
+/// Checks the session cookie, refreshing it if it expired.
+pub(crate) async fn has_session(api_url: &str) -> bool {
     let client = reqwest::Client::new();
 
     match client
-        .get(format!("{}healthz", api_url))
-        .header("authorization", ["Bearer ", bearer.as_str()].concat())
+        .get(format!("{}whoami", api_url))
+        .with_credentials()
         .send()
         .await
     {
-        Ok(res) => {
-            if !res.status().is_success() {
-                error!("Login failed: {}", res.text().await.unwrap());
-                nav.replace(Route::Login {});
-                return;
+        Ok(res) if res.status().is_success() => true,
+        Ok(_) => match client
+            .post(format!("{}refresh", api_url))
+            .with_credentials()
+            .send()
+            .await
+        {
+            Ok(res) => {
+                if res.status().is_success() {
+                    info!("Session refreshed");
+                }
+                res.status().is_success()
             }
-            info!("Login successful");
-        }
+            Err(e) => {
+                error!("Session refresh failed: {}", e);
+                false
+            }
+        },
         Err(e) => {
             error!("Login failed: {}", e);
-            nav.replace(Route::Login {});
+            false
         }
     }
 }
+
+pub(crate) async fn check_login(api_url: String) {
+    let nav = navigator();
+    if has_session(&api_url).await {
+        info!("Login successful");
+    } else {
+        error!("Not logged in");
+        nav.replace(Route::Login {});
+    }
+}
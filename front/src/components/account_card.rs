@@ -1,131 +1,100 @@
+use crate::api::{error_message, WithCredentials};
 use crate::routes::Route;
-use crate::types::{AppContext, Config, ParkingSession, RenewSession};
+use crate::types::{AccountStatus, AppContext, ParkingSession, PlanChange, RenewSession};
 use chrono::{DateTime, Datelike};
 use chrono_tz::Europe::Paris;
 use dioxus::prelude::*;
 use dioxus_logger::tracing::{error, info};
-use dioxus_sdk_storage::use_persistent;
+
+/// Formats a time of the API in the local time zone, with the date when it is not today.
+fn format_time(time: &str) -> String {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => {
+            let local_time = time.with_timezone(&Paris);
+            if local_time.num_days_from_ce() > chrono::Local::now().num_days_from_ce() {
+                local_time.format("%d/%m/%Y - %H:%M").to_string()
+            } else {
+                local_time.format("%H:%M").to_string()
+            }
+        }
+        Err(e) => {
+            error!("Failed to parse time {}: {}", time, e);
+            "".to_string()
+        }
+    }
+}
 
 #[component]
-pub(crate) fn AccountCard_comp(account: Config) -> Element {
-    let bearer = use_persistent("bearer", || "".to_string());
-    let acc = account.clone();
-    let mut session = use_signal(ParkingSession::default);
-    let mut loading_session = use_signal(|| true);
-    let mut renew_time = use_signal(|| "".to_string());
-    let mut renew_duration = use_signal(|| "".to_string());
-    let mut start_time = use_signal(|| "".to_string());
-    let mut expiry_time = use_signal(|| "".to_string());
+pub(crate) fn AccountCard_comp(
+    status: AccountStatus,
+    renewal_error: Option<String>,
+    on_change: EventHandler,
+) -> Element {
+    let account = status.clone();
+    let pause_name = account.name.clone();
+    let session = status
+        .session
+        .clone()
+        .unwrap_or_else(ParkingSession::default);
+    let start_time = format_time(&session.start_time);
+    let expiry_time = format_time(&session.expire_time);
+    let (renew_time, renew_duration) = match &status.plan {
+        Some(plan) => {
+            let duration = chrono::Duration::minutes(plan.duration as i64);
+            (
+                format_time(&plan.next_check),
+                format!(
+                    "{:02}:{:02}",
+                    duration.num_hours(),
+                    duration.num_minutes() % 60
+                ),
+            )
+        }
+        None => ("".to_string(), "".to_string()),
+    };
+    let updated = status.fetched.as_deref().map(format_time);
+    let paused = status.plan.as_ref().is_some_and(|p| p.paused);
+    let mut loading_pause = use_signal(|| false);
     let context = use_context::<Signal<AppContext>>();
 
-    use_resource(move || {
-        let account = acc.clone();
-        async move {
-            {
-                info!("Checking account: {}", account.name);
-                let client = reqwest::Client::new();
-
-                match client
-                    .get(format!(
-                        "{}/check?name={}",
-                        context.read().api_url,
-                        account.name
-                    ))
-                    .header("authorization", ["Bearer ", bearer().as_str()].concat())
-                    .send()
-                    .await
-                {
-                    Ok(res) => match res.text().await {
-                        Ok(json) => match serde_json::from_str::<ParkingSession>(&json) {
-                            Ok(sess) => {
-                                match DateTime::parse_from_rfc3339(sess.start_time.as_str()) {
-                                    Ok(time) => {
-                                        let local_time = time.with_timezone(&Paris);
-                                        start_time.set(local_time.format("%H:%M").to_string());
-                                    }
-                                    Err(e) => {
-                                        error!("Failed to parse start time: {}", e);
-                                        return;
-                                    }
-                                }
-                                match DateTime::parse_from_rfc3339(sess.expire_time.as_str()) {
-                                    Ok(time) => {
-                                        let local_time = time.with_timezone(&Paris);
-                                        if local_time.num_days_from_ce()
-                                            > chrono::Local::now().num_days_from_ce()
-                                        {
-                                            expiry_time.set(
-                                                local_time.format("%d/%m/%Y - %H:%M").to_string(),
-                                            );
-                                        } else {
-                                            expiry_time.set(local_time.format("%H:%M").to_string());
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Failed to parse end time: {}", e);
-                                        return;
-                                    }
-                                }
-                                session.set(sess);
-                            }
-                            Err(e) => {
-                                error!("Failed to parse parking session: {}", e);
-                            }
-                        },
-                        Err(e) => {
-                            error!("Can't check account {}: {}", account.name, e);
-                        }
-                    },
-                    Err(e) => {
-                        error!("Can't check account {}: {}", account.name, e);
-                    }
-                };
-                match client
-                    .get(format!(
-                        "{}/check_renew?name={}",
-                        context.read().api_url,
-                        account.name
-                    ))
-                    .header("authorization", ["Bearer ", bearer().as_str()].concat())
-                    .send()
-                    .await
-                {
-                    Ok(res) => match res.text().await {
-                        Ok(json) => match serde_json::from_str::<RenewSession>(&json) {
-                            Ok(sess) => {
-                                match DateTime::parse_from_rfc3339(sess.next_check.as_str()) {
-                                    Ok(time) => {
-                                        let local_time = time.with_timezone(&Paris);
-                                        renew_time.set(local_time.format("%H:%M").to_string());
-                                    }
-                                    Err(e) => {
-                                        error!("Failed to parse start time: {}", e);
-                                        return;
-                                    }
-                                }
-                                let renew_time = chrono::Duration::minutes(sess.duration as i64);
-                                renew_duration.set(format!(
-                                    "{:02}:{:02}",
-                                    renew_time.num_hours(),
-                                    renew_time.num_minutes() % 60
-                                ));
-                            }
-                            Err(e) => {
-                                error!("Failed to parse renew session: {}", e);
-                            }
-                        },
-                        Err(e) => {
-                            error!("Can't check renew account {}: {}", account.name, e);
-                        }
-                    },
-                    Err(e) => {
-                        error!("Can't check renew account {}: {}", account.name, e);
-                    }
+    let toggle_pause = move |name: String| async move {
+        loading_pause.set(true);
+        let action = if paused { "resume" } else { "pause" };
+        let client = reqwest::Client::new();
+        match client
+            .patch(format!(
+                "{}v1/accounts/{}/plan",
+                context.read().api_url,
+                name
+            ))
+            .with_credentials()
+            .json(&PlanChange { paused: !paused })
+            .send()
+            .await
+        {
+            Ok(res) if res.status().is_success() => match res.json::<RenewSession>().await {
+                Ok(sess) => {
+                    info!("Renewal of {} is now paused: {}", name, sess.paused);
+                    on_change.call(());
                 }
-                loading_session.set(false);
+                Err(e) => {
+                    error!("Failed to parse renew session: {}", e);
+                }
+            },
+            Ok(res) => {
+                error!(
+                    "Can't {} account {}: {}",
+                    action,
+                    name,
+                    error_message(res).await
+                );
+            }
+            Err(e) => {
+                error!("Can't {} account {}: {}", action, name, e);
             }
         }
-    });
+        loading_pause.set(false);
+    };
 
     rsx! {
     div { class: "card",
@@ -148,33 +117,40 @@ pub(crate) fn AccountCard_comp(account: Config) -> Element {
                         }
                     }
                     div {
-                        if loading_session() {
-                            div {class: "skeleton-block"}
+                        if session.expire_time.is_empty() {
+                            p { class: "title is-4", "Not parked" }
                         } else {
-                            if session().expire_time.is_empty() {
-                                p { class: "title is-4", "Not parked" }
-                            } else {
-                                p { class: "title is-4 is-spaced", "Session" }
-                                div { class: "subtitle is-flex",
-                                    div { class: "mx-2",
-                                        p { "Start: {start_time}" }
-                                        p { class: "has-text-danger", "End: {expiry_time}" }
-                                    }
-                                    div { class: "mx-2",
+                            p { class: "title is-4 is-spaced", "Session" }
+                            div { class: "subtitle is-flex",
+                                div { class: "mx-2",
+                                    p { "Start: {start_time}" }
+                                    p { class: "has-text-danger", "End: {expiry_time}" }
+                                }
+                                div { class: "mx-2",
+                                    if paused {
+                                        p { class: "has-text-warning", "Renewal paused" }
+                                    } else {
                                         p { "Next renew: {renew_time}" }
-                                        p { "For at least: {renew_duration}" }
                                     }
+                                    p { "For at least: {renew_duration}" }
                                 }
                             }
                         }
+                        if let Some(error) = &renewal_error {
+                            p { class: "has-text-danger is-size-7", "Renewal failed: {error}" }
+                        }
+                        if let Some(error) = &status.error {
+                            p { class: "has-text-danger is-size-7", "{error}" }
+                        }
+                        if let Some(updated) = &updated {
+                            p { class: "has-text-grey is-size-7", "Updated at {updated}" }
+                        }
                     }
                 }
                 footer { class: "card-footer",
                     Link {
                         class: {
-                            if loading_session() {
-                                "card-footer-item button is-primary is-loading"
-                            } else if !session().expire_time.is_empty() {
+                            if !session.expire_time.is_empty() {
                                 "card-footer-item button is-primary is-static"
                             } else {
                                 "card-footer-item button is-primary"
@@ -184,6 +160,17 @@ pub(crate) fn AccountCard_comp(account: Config) -> Element {
                         },
                         "Park"
                     }
+                    if !renew_time.is_empty() {
+                        button {
+                            class: if loading_pause() {
+                                "card-footer-item button is-warning is-loading"
+                            } else {
+                                "card-footer-item button is-warning"
+                            },
+                            onclick: move |_| toggle_pause(pause_name.clone()),
+                            if paused { "Resume" } else { "Pause" }
+                        }
+                    }
                 }
             }
         }
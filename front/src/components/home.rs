@@ -1,52 +1,99 @@
+use crate::api::WithCredentials;
 use crate::check_login::check_login;
 use crate::components::account_card::AccountCard_comp;
-use crate::types::{Accounts, AppContext, Config};
+use crate::events;
+use crate::routes::Route;
+use crate::types::{AccountStatus, AppContext, Status, Update};
 use dioxus::core_macro::{component, rsx};
 use dioxus::dioxus_core::Element;
 use dioxus::hooks::{use_context, use_resource, use_signal};
 use dioxus::prelude::Signal;
 use dioxus::prelude::*;
-use dioxus_logger::tracing::error;
-use dioxus_sdk_storage::use_persistent;
+use dioxus_logger::tracing::{error, info};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 #[component]
 pub(crate) fn Home() -> Element {
-    let bearer = use_persistent("bearer", || "".to_string());
-    let mut accounts = use_signal(Vec::<Config>::new);
+    let mut accounts = use_signal(Vec::<AccountStatus>::new);
+    let mut refresh = use_signal(|| 0u32);
+    let mut renewal_errors = use_signal(HashMap::<String, String>::new);
     let context = use_context::<Signal<AppContext>>();
-    let api_url = context.read().api_url.clone();
+    let nav = navigator();
     use_resource(move || async move {
+        // Fetched again whenever an update is pushed by the backend.
+        refresh();
+        check_login(context.read().api_url.clone()).await;
         let client = reqwest::Client::new();
         match client
-            .get(format!("{}accounts", context.read().api_url))
-            .header("authorization", ["Bearer ", bearer().as_str()].concat())
+            .get(format!("{}status", context.read().api_url))
+            .with_credentials()
             .send()
             .await
         {
             Ok(res) => match res.text().await {
-                Ok(json) => match serde_json::from_str::<Accounts>(&json) {
-                    Ok(acc) => {
-                        accounts.set(acc.accounts);
+                Ok(json) => match serde_json::from_str::<Status>(&json) {
+                    Ok(status) => {
+                        accounts.set(status.accounts);
                     }
                     Err(e) => {
-                        error!("Failed to parse accounts: {}", e);
+                        error!("Failed to parse status: {}", e);
                     }
                 },
                 Err(e) => {
-                    error!("Failed to parse accounts: {}", e);
+                    error!("Failed to parse status: {}", e);
                 }
             },
             Err(e) => {
-                error!("Failed to fetch accounts: {}", e);
+                error!("Failed to fetch status: {}", e);
             }
         }
     });
 
+    let updates = use_coroutine(move |mut updates: UnboundedReceiver<Update>| async move {
+        while let Some(update) = updates.next().await {
+            info!("Update: {:?}", update);
+            if let Some(account) = update.account {
+                if update.kind == "renewal_failed" {
+                    renewal_errors
+                        .write()
+                        .insert(account, update.error.unwrap_or_default());
+                } else {
+                    renewal_errors.write().remove(&account);
+                }
+            }
+            refresh += 1;
+        }
+    });
+    // Kept open as long as the page is displayed.
+    use_hook(|| Rc::new(events::subscribe(&context.read().api_url, updates.tx())));
+
+    let logout = move |_| async move {
+        let client = reqwest::Client::new();
+        match client
+            .post(format!("{}logout", context.read().api_url))
+            .with_credentials()
+            .send()
+            .await
+        {
+            Ok(_) => info!("Logged out"),
+            Err(e) => error!("Failed to log out: {}", e),
+        }
+        nav.replace(Route::Login {});
+    };
+
     rsx! {
-        div { class: "container is-max-tablet", onmounted: move |_| check_login(bearer(), api_url.clone()),
+        div { class: "container is-max-tablet",
             h1 { class: "is-size-1 has-text-centered", "Accounts" }
-            for account in accounts() {
-                AccountCard_comp { account: account }
+            button { class: "button is-small is-light is-pulled-right", onclick: logout, "Logout" }
+            for status in accounts() {
+                AccountCard_comp {
+                    key: "{status.name}",
+                    renewal_error: renewal_errors.read().get(&status.name).cloned(),
+                    status: status.clone(),
+                    on_change: move |_| refresh += 1,
+                }
             }
         }
     }
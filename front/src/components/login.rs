@@ -1,39 +1,49 @@
+use crate::api::WithCredentials;
+use crate::check_login::has_session;
 use crate::routes::Route;
-use crate::types::AppContext;
+use crate::types::{AppContext, Credentials};
 use dioxus::prelude::*;
 use dioxus_logger::tracing::{error, info};
 use dioxus_sdk_storage::use_persistent;
 
 #[component]
 pub(crate) fn Login() -> Element {
-    let mut bearer = use_persistent("bearer", || "".to_string());
+    let mut username = use_persistent("username", || "".to_string());
+    let mut password = use_signal(|| "".to_string());
+    let mut failed = use_signal(|| false);
     let nav = navigator();
     let context = use_context::<Signal<AppContext>>();
     let mut loading = use_signal(|| false);
     let login = move || {
         spawn(async move {
-            info!("Bearer token: {}", bearer());
             loading.set(true);
             let client = reqwest::Client::new();
 
             match client
-                .get(format!("{}healthz", context.read().api_url))
-                .header("authorization", ["Bearer ", bearer().as_str()].concat())
+                .post(format!("{}login", context.read().api_url))
+                .with_credentials()
+                .json(&Credentials {
+                    username: username(),
+                    password: password(),
+                })
                 .send()
                 .await
             {
                 Ok(res) => {
                     if !res.status().is_success() {
                         error!("Login failed: {}", res.text().await.unwrap());
+                        failed.set(true);
                         loading.set(false);
                         return;
                     }
                     info!("Login successful");
+                    password.set("".to_string());
                     loading.set(false);
                     nav.replace(Route::Home {});
                 }
                 Err(e) => {
                     error!("Login failed: {}", e);
+                    failed.set(true);
                     loading.set(false);
                 }
             }
@@ -42,16 +52,25 @@ pub(crate) fn Login() -> Element {
 
     rsx! {
         div { class: "container small-container", onvisible: move |_| {
-            if !bearer().is_empty() {
-                info!("Bearer token found, checking...");
-                login();
-            }
+            spawn(async move {
+                if has_session(&context.read().api_url).await {
+                    info!("Session found, skipping login");
+                    nav.replace(Route::Home {});
+                }
+            });
         },
         h1 { class: "is-size-1 has-text-centered", "Login" }
         form { onsubmit: move |_| login(),
             div { class: "field",
-                label { class: "label", "Bearer token" }
-                input { name: "Bearer token", class: "input", placeholder: "Bearer token", required: true, oninput: move |e| bearer.set(e.value()), disabled: loading(), value: bearer() }
+                label { class: "label", "Username" }
+                input { name: "username", class: "input", placeholder: "Username", autocomplete: "username", required: true, oninput: move |e| username.set(e.value()), disabled: loading(), value: username() }
+                }
+            div { class: "field",
+                label { class: "label", "Password" }
+                input { r#type: "password", name: "password", class: "input", placeholder: "Password", autocomplete: "current-password", required: true, oninput: move |e| password.set(e.value()), disabled: loading(), value: password() }
+                if failed() {
+                    p { class: "help is-danger", "Invalid username or password" }
+                }
                 }
             input { r#type: "submit", disabled: loading(), class:"button is-primary is-fullwidth", "Login" }
             }
@@ -1,3 +1,4 @@
+use crate::api::{error_message, WithCredentials};
 use crate::check_login::check_login;
 use crate::routes::Route;
 use crate::types;
@@ -10,22 +11,28 @@ use dioxus::hooks::{use_context, use_signal};
 use dioxus::prelude::Signal;
 use dioxus::prelude::*;
 use dioxus_logger::tracing::{error, info};
-use dioxus_sdk_storage::use_persistent;
 
 #[component]
 pub(crate) fn Park(name: String) -> Element {
-    let bearer = use_persistent("bearer", || "".to_string());
     let context = use_context::<Signal<AppContext>>();
     let api_url = context.read().api_url.clone();
     let mut duration = use_signal(|| "".to_string());
     let mut end_text = use_signal(|| "".to_string());
     let mut danger_text = use_signal(|| "Invalid time".to_string());
+    let mut price_text = use_signal(|| "".to_string());
     let mut loading_button = use_signal(|| "".to_string());
     let mut disabled_button = use_signal(|| true);
     let mut park_code = use_signal(|| 0);
+    let mut error_text = use_signal(|| "".to_string());
+    // Kept for retries until the server parks, so that a retry after a network error is not paid
+    // twice, and renewed when the form changes.
+    let mut idempotency_key = use_signal(new_idempotency_key);
 
+    let quote_name = name.clone();
     let check_time = move |e: Event<FormData>| {
         duration.set(e.value());
+        idempotency_key.set(new_idempotency_key());
+        price_text.set("".to_string());
         let dur = match NaiveTime::parse_from_str(e.value().as_str(), "%H:%M") {
             Ok(dura) => dura,
             Err(_) => {
@@ -59,6 +66,47 @@ pub(crate) fn Park(name: String) -> Element {
                 time_final.format("%H:%M")
             ));
         }
+
+        let requested = e.value();
+        let minutes = dur.minute() as i16 + dur.hour() as i16 * 60;
+        let name = quote_name.clone();
+        spawn(async move {
+            let client = reqwest::Client::new();
+            let estimate = match client
+                .get(format!(
+                    "{}v1/accounts/{}/quote?duration={}",
+                    context.read().api_url,
+                    name,
+                    minutes
+                ))
+                .with_credentials()
+                .send()
+                .await
+            {
+                Ok(res) if res.status().is_success() => res.json::<types::Estimate>().await,
+                Ok(res) => {
+                    error!("Failed to get quote: {}", error_message(res).await);
+                    return;
+                }
+                Err(e) => Err(e),
+            };
+            // Another duration may have been typed in the meantime.
+            if duration() != requested {
+                return;
+            }
+            match estimate {
+                Ok(estimate) => price_text.set(format!(
+                    "Estimated cost: {:.2} {} for {} purchases, covered until {}",
+                    estimate.total_cost.amount,
+                    estimate.total_cost.currency,
+                    estimate.purchases.len(),
+                    chrono::DateTime::parse_from_rfc3339(&estimate.covered_until)
+                        .map(|t| t.with_timezone(&chrono::Local).format("%H:%M").to_string())
+                        .unwrap_or(estimate.covered_until)
+                )),
+                Err(e) => error!("Failed to get quote: {}", e),
+            }
+        });
     };
 
     let post_park = move |name: String| async move {
@@ -68,18 +116,22 @@ pub(crate) fn Park(name: String) -> Element {
 
         let client = reqwest::Client::new();
         match client
-            .post(format!("{}park", context.read().api_url))
-            .header("authorization", ["Bearer ", bearer().as_str()].concat())
-            .json(&types::Parking {
-                name: name.clone(),
-                duration: dur,
-            })
+            .post(format!(
+                "{}v1/accounts/{}/session",
+                context.read().api_url,
+                name
+            ))
+            .with_credentials()
+            .header("Idempotency-Key", idempotency_key())
+            .json(&types::NewSession { duration: dur })
             .send()
             .await
         {
             Ok(res) => {
                 if !res.status().is_success() {
-                    error!("Park failed: {}", res.text().await.unwrap());
+                    let message = error_message(res).await;
+                    error!("Park failed: {}", message);
+                    error_text.set(message);
                     park_code.set(500);
                     loading_button.set("".to_string());
                     return;
@@ -87,9 +139,11 @@ pub(crate) fn Park(name: String) -> Element {
                 info!("Park successful");
                 loading_button.set("".to_string());
                 park_code.set(res.status().into());
+                idempotency_key.set(new_idempotency_key());
             }
             Err(e) => {
                 error!("Park failed: {}", e);
+                error_text.set("Please retry later".to_string());
                 park_code.set(500);
                 loading_button.set("".to_string());
             }
@@ -97,7 +151,7 @@ pub(crate) fn Park(name: String) -> Element {
     };
 
     rsx! {
-    div { class: "container is-max-tablet", onmounted: move |_| check_login(bearer(), api_url.clone()),
+    div { class: "container is-max-tablet", onmounted: move |_| check_login(api_url.clone()),
         h1 { class: "is-size-1 has-text-centered", "Park ",
                 span { class: "has-text-weight-bold has-text-primary", "{name}"}
             }
@@ -106,13 +160,14 @@ pub(crate) fn Park(name: String) -> Element {
                 label { class: "label", "Duration" }
                 input { r#type: "time", class: "input", placeholder: "Time", required: true, name: "time", oninput: check_time }
                 p { class: "help", "{end_text}" }
+                p { class: "help", "{price_text}" }
                 p { class: "help is-danger", "{danger_text}" }
                 }
              button { disabled: disabled_button(), class:"button is-primary is-fullwidth {loading_button}", "Park" }
             }
 
         if park_code() !=0 {
-            if park_code() == 202 {
+            if park_code() == 201 {
                 div { class: "notification is-success mt-3",
                     button { class: "delete", onclick: move |_| park_code.set(0)}
                     p { class:"is-flex is-align-items-center", "Parking successful! {end_text}! You can now" }
@@ -120,13 +175,25 @@ pub(crate) fn Park(name: String) -> Element {
                     class: "button is-primary is-fullwidth", to: Route::Home {}, "Go back"}
                 }
             }
+            else if park_code() == 200 {
+                div { class: "notification is-info mt-3",
+                    button { class: "delete", onclick: move |_| park_code.set(0)}
+                    p { class:"is-flex is-align-items-center", "Dry run, nothing was paid. {price_text}" }
+                        Link {
+                    class: "button is-primary is-fullwidth", to: Route::Home {}, "Go back"}
+                }
+            }
             else {
                  div { class: "notification is-danger mt-3",
                     button { class: "delete", onclick: move |_| park_code.set(0)}
-                    p { class: "is-flex is-align-items-center", "Parking Failed! Please retry later"}
+                    p { class: "is-flex is-align-items-center", "Parking Failed! {error_text}"}
                     }
             }
         }
     }
     }
 }
+
+fn new_idempotency_key() -> String {
+    format!("park-{}", chrono::Utc::now().timestamp_millis())
+}
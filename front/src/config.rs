@@ -0,0 +1,24 @@
+use crate::api::resolve_url;
+use crate::types::FrontConfig;
+use dioxus_logger::tracing::{error, info};
+
+/// Loads the config.json served next to the frontend, defaulting to the API of the same origin.
+pub(crate) async fn load() -> FrontConfig {
+    match reqwest::get(resolve_url("/config.json")).await {
+        Ok(res) if res.status().is_success() => match res.json::<FrontConfig>().await {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to parse config.json: {}", e);
+                FrontConfig::default()
+            }
+        },
+        Ok(res) => {
+            info!("No config.json ({}), using defaults", res.status());
+            FrontConfig::default()
+        }
+        Err(e) => {
+            error!("Failed to fetch config.json: {}", e);
+            FrontConfig::default()
+        }
+    }
+}
@@ -1,3 +0,0 @@
-// This file is automatically generated by build.rs
-
-pub const API_URL: &str = "http://localhost:3000/api/";
@@ -0,0 +1,61 @@
+use crate::types::Update;
+use dioxus::prelude::UnboundedSender;
+
+/// Connection to the event stream of the backend, closed when dropped.
+pub(crate) struct Subscription {
+    #[cfg(target_arch = "wasm32")]
+    source: web_sys::EventSource,
+    #[cfg(target_arch = "wasm32")]
+    _on_message: wasm_bindgen::closure::Closure<dyn FnMut(web_sys::MessageEvent)>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.source.close();
+    }
+}
+
+/// Sends every update pushed by the backend to the channel. The browser reconnects on its own when
+/// the stream is interrupted.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn subscribe(api_url: &str, updates: UnboundedSender<Update>) -> Option<Subscription> {
+    use dioxus_logger::tracing::error;
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+
+    let init = web_sys::EventSourceInit::new();
+    init.set_with_credentials(true);
+    let source = match web_sys::EventSource::new_with_event_source_init_dict(
+        &format!("{}events", api_url),
+        &init,
+    ) {
+        Ok(source) => source,
+        Err(e) => {
+            error!("Failed to open the event stream: {:?}", e);
+            return None;
+        }
+    };
+    let on_message =
+        Closure::<dyn FnMut(web_sys::MessageEvent)>::new(move |message: web_sys::MessageEvent| {
+            let Some(data) = message.data().as_string() else {
+                return;
+            };
+            match serde_json::from_str::<Update>(&data) {
+                Ok(update) => {
+                    let _ = updates.unbounded_send(update);
+                }
+                Err(e) => error!("Failed to parse update: {}", e),
+            }
+        });
+    source.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    Some(Subscription {
+        source,
+        _on_message: on_message,
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn subscribe(_api_url: &str, _updates: UnboundedSender<Update>) -> Option<Subscription> {
+    None
+}
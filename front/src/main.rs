@@ -1,9 +1,12 @@
+mod api;
 mod check_login;
 mod components;
-mod env;
+mod config;
+mod events;
 mod routes;
 mod types;
 
+use crate::api::resolve_url;
 use crate::routes::Route;
 use crate::types::AppContext;
 use dioxus::prelude::*;
@@ -17,15 +20,23 @@ fn main() {
 }
 
 fn app() -> Element {
-    info!("using API URL: {}", env::API_URL);
-    use_context_provider(|| {
+    let mut context = use_context_provider(|| {
         Signal::new(AppContext {
-            api_url: env::API_URL.parse().unwrap(),
+            api_url: String::new(),
         })
     });
+    let config = use_resource(move || async move {
+        let config = config::load().await;
+        info!("using API URL: {}", config.api_url);
+        context.write().api_url = resolve_url(&config.api_url);
+    });
 
     rsx! {
         document::Link { rel: "icon", href: FAVICON }
-        Router::<Route> {}
+        if config.read().is_some() {
+            Router::<Route> {}
+        } else {
+            div { class: "container is-max-tablet", div { class: "skeleton-block" } }
+        }
     }
 }
@@ -1,29 +1,61 @@
 use chrono::{DateTime, Utc};
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
 
 pub(crate) struct AppContext {
     pub(crate) api_url: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct Accounts {
-    pub(crate) accounts: Vec<Config>,
+/// Runtime configuration of the frontend, served as config.json next to it.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct FrontConfig {
+    #[serde(default = "default_api_url")]
+    pub(crate) api_url: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub(crate) struct Config {
-    pub(crate) name: String,
-    pub(crate) plate: String,
-    pub(crate) lot: i32,
+/// The API of the backend serving the frontend.
+fn default_api_url() -> String {
+    "/api/".to_string()
+}
+
+impl Default for FrontConfig {
+    fn default() -> Self {
+        Self {
+            api_url: default_api_url(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
-pub(crate) struct Parking {
-    pub(crate) name: String,
+pub(crate) struct Credentials {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+/// Session started on an account by `POST v1/accounts/{name}/session`.
+#[derive(Serialize)]
+pub(crate) struct NewSession {
     pub(crate) duration: i16,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Pause or resumption of a renewal plan by `PATCH v1/accounts/{name}/plan`.
+#[derive(Serialize)]
+pub(crate) struct PlanChange {
+    pub(crate) paused: bool,
+}
+
+/// Error envelope of the v1 API.
+#[derive(Deserialize)]
+pub(crate) struct ApiError {
+    pub(crate) error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ErrorDetail {
+    pub(crate) message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct RateOption {
     #[serde(rename = "rateOptionId")]
     rate_option_id: String,
@@ -40,7 +72,7 @@ impl RateOption {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct Segment {
     #[serde(rename = "chargeableTimeUnitType")]
     chargeable_time_unit_type: i32,
@@ -58,10 +90,10 @@ pub(crate) struct Segment {
     parking_start: DateTime<Utc>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct TotalCost {
-    amount: f64,
-    currency: String,
+    pub(crate) amount: f64,
+    pub(crate) currency: String,
 }
 
 impl TotalCost {
@@ -73,7 +105,7 @@ impl TotalCost {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct ParkedVehicle {
     #[serde(rename = "countryCode")]
     country_code: String,
@@ -97,7 +129,7 @@ impl ParkedVehicle {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct ParkingSession {
     #[serde(rename = "couponApplied")]
     coupon_applied: Option<String>,
@@ -153,9 +185,44 @@ impl ParkingSession {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub(crate) struct RenewSession {
     #[serde(rename = "nextCheck")]
     pub(crate) next_check: String,
     pub(crate) duration: i16,
+    #[serde(default)]
+    pub(crate) paused: bool,
+}
+
+/// Cost of parking until a given end, renewals included.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Estimate {
+    pub(crate) covered_until: String,
+    pub(crate) purchases: Vec<IgnoredAny>,
+    pub(crate) total_cost: TotalCost,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Status {
+    pub(crate) accounts: Vec<AccountStatus>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct AccountStatus {
+    pub(crate) name: String,
+    pub(crate) plate: String,
+    pub(crate) lot: i32,
+    pub(crate) plan: Option<RenewSession>,
+    pub(crate) session: Option<ParkingSession>,
+    pub(crate) error: Option<String>,
+    pub(crate) fetched: Option<String>,
+}
+
+/// Change pushed by the backend on `/events`.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Update {
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+    pub(crate) account: Option<String>,
+    pub(crate) error: Option<String>,
 }